@@ -8,37 +8,33 @@ use std::io::{stdout, Write};
 use std::thread;
 use std::time;
 
-struct CounterHistory {
-    key: stas::CounterKey,
-    history: Vec<u64>,
-    curr: Option<u64>,
-    prev: Option<u64>,
-    base: u64,
-    age: u32,
-    unit: stas::UnitChain,
-    filter: Vec<Box<dyn stas::CounterValueFilter>>,
-}
-
 fn show_help_exit(rc: i32) {
     println!("Usage: stas [ethtool:] @eth* @ens* [...] tx_* rx_* /B/bps");
     std::process::exit(rc);
 }
 
 struct CounterLine<'a> {
-    key: &'a stas::CounterKey,
+    history: &'a stas::CounterHistory,
     value: Option<stas::Value>,
     avg: Option<stas::Value>,
     freq: &'a stas::UFreq,
     unit: stas::Unit,
-    filter: &'a Vec<Box<dyn stas::CounterValueFilter>>,
 }
 
 trait CounterListFilter {
-    fn filter<'a>(&self, counters: Vec<CounterLine<'a>>) -> Vec<CounterLine<'a>>;
+    fn filter<'a>(
+        &self,
+        counters: Vec<CounterLine<'a>>,
+        state: &[&stas::CounterHistory],
+    ) -> Vec<CounterLine<'a>>;
 }
 
 impl CounterListFilter for stas::NonZeroCounterFilter {
-    fn filter<'a>(&self, mut counters: Vec<CounterLine<'a>>) -> Vec<CounterLine<'a>> {
+    fn filter<'a>(
+        &self,
+        mut counters: Vec<CounterLine<'a>>,
+        _state: &[&stas::CounterHistory],
+    ) -> Vec<CounterLine<'a>> {
         counters
             .drain(..)
             .filter(|cl| self.do_filter(&cl.value, &cl.avg))
@@ -50,10 +46,19 @@ impl CounterListFilter for stas::NonZeroCounterFilter {
 struct ApplyValueFilters {}
 
 impl CounterListFilter for ApplyValueFilters {
-    fn filter<'a>(&self, mut counters: Vec<CounterLine<'a>>) -> Vec<CounterLine<'a>> {
+    fn filter<'a>(
+        &self,
+        mut counters: Vec<CounterLine<'a>>,
+        state: &[&stas::CounterHistory],
+    ) -> Vec<CounterLine<'a>> {
         counters
             .drain(..)
-            .filter(|cl| cl.filter.iter().all(|vf| vf.filter(&cl.value, &cl.avg)))
+            .filter(|cl| {
+                cl.history
+                    .filter
+                    .iter()
+                    .all(|vf| vf.filter(&cl.value, &cl.avg, &cl.unit, cl.history, state))
+            })
             .collect()
     }
 }
@@ -63,6 +68,7 @@ fn main() {
     list_filters.push(Box::new(ApplyValueFilters {}));
     let mut once: bool = false;
     let rules;
+    let mut warnings = Vec::new();
 
     {
         let mut args: Vec<String> = env::args().collect();
@@ -95,13 +101,21 @@ fn main() {
             }
         }
 
+        let expr: Vec<String> = it.clone().cloned().collect();
         match stas::parse_expr(&mut it) {
             Ok(r) => rules = r,
             Err(e) => {
                 println!("Error: {}", e);
+                println!("{}", e.show(&expr));
                 std::process::exit(1);
             }
         }
+
+        // Likely typos are shown under the table, but don't stop anything.
+        for warning in stas::check_names(&expr, &rules) {
+            warnings.push(format!("Warning: {}", warning));
+            warnings.extend(warning.show(&expr).lines().map(|line| line.to_string()));
+        }
     }
 
     let cycle_ms = 500;
@@ -110,10 +124,11 @@ fn main() {
     let avg_s_str = &format!("{}s avg", avg_s);
 
     //print!("{}", termion::clear::All);
-    let mut state = Vec::<CounterHistory>::new();
+    let mut state = Vec::<stas::CounterHistory>::new();
     let mut nlines = 0;
     loop {
         let start = std::time::Instant::now();
+        stas::new_tick();
 
         // Trim the history at history_depth.
         for entry in &mut state {
@@ -141,11 +156,15 @@ fn main() {
                     for imm in imms {
                         if let Some(elem) = state.iter_mut().find(|hist| hist.key == imm.key) {
                             elem.curr = Some(imm.value);
+                            if imm.value != elem.base {
+                                elem.changed = true;
+                            }
                         } else {
-                            state.push(CounterHistory {
+                            state.push(stas::CounterHistory {
                                 key: imm.key,
                                 history: vec![],
                                 base: imm.value,
+                                changed: false,
                                 curr: Some(imm.value),
                                 prev: None,
                                 age: 0,
@@ -176,7 +195,9 @@ fn main() {
                 };
 
             let value = match entry.unit.freq {
-                stas::UFreq::AsIs => entry.curr.map(|v| stas::Value::from_num(v)),
+                stas::UFreq::AsIs | stas::UFreq::Rate => {
+                    entry.curr.map(|v| stas::Value::from_num(v))
+                }
                 stas::UFreq::Delta => entry
                     .curr
                     .map(|v| stas::Value::from_num(v) - stas::Value::from_num(entry.base)),
@@ -191,17 +212,19 @@ fn main() {
 
             let (value, avg, unit) = stas::convert(&entry.unit, value, avg);
             counter_lines.push(CounterLine {
-                key: &entry.key,
+                history: entry,
                 value: value,
                 avg: avg,
                 freq: &entry.unit.freq,
                 unit: unit,
-                filter: &entry.filter,
             });
         }
 
+        // Filters that look at other counters, such as stuck, see all of them, not only those
+        // that passed the filters before.
+        let all: Vec<&stas::CounterHistory> = state.iter().collect();
         for cf in &list_filters {
-            counter_lines = cf.filter(counter_lines);
+            counter_lines = cf.filter(counter_lines, &all);
         }
 
         print!("{}", termion::cursor::Goto(1, 1));
@@ -268,24 +291,33 @@ fn main() {
         print!("{}", termion::style::Reset);
 
         for counter_line in &counter_lines {
+            // A share of time per second, or of a rate limit, is just a ratio.
             let unit_str = counter_line.unit.base.to_string()
                 + match counter_line.freq {
                     stas::UFreq::AsIs => "  ",
                     stas::UFreq::Delta => "  ",
-                    stas::UFreq::PerSecond => "ps",
+                    stas::UFreq::PerSecond | stas::UFreq::Rate
+                        if counter_line.unit.base.is_ratio() =>
+                    {
+                        "  "
+                    }
+                    stas::UFreq::PerSecond | stas::UFreq::Rate => "ps",
                 };
             let unit_prefix_str = match counter_line.freq {
                 stas::UFreq::AsIs => " ",
                 stas::UFreq::Delta => "\u{0394}",
-                stas::UFreq::PerSecond => " ",
+                stas::UFreq::PerSecond | stas::UFreq::Rate => " ",
             };
 
+            let fixed = counter_line.history.unit.fixed_prefix || counter_line.unit.base.is_ratio();
+
             line_out(
-                &counter_line.key.key,
+                &counter_line.history.key.key,
                 &if counter_line.value.is_some() {
                     stas::humanize(
                         counter_line.value.unwrap(),
                         counter_line.unit.prefix,
+                        fixed,
                         &unit_prefix_str,
                         &unit_str,
                         false,
@@ -297,6 +329,7 @@ fn main() {
                     stas::humanize(
                         counter_line.avg.unwrap(),
                         counter_line.unit.prefix,
+                        fixed,
                         &unit_prefix_str,
                         &unit_str,
                         true,
@@ -313,6 +346,10 @@ fn main() {
             termion::clear::CurrentLine,
             start.elapsed()
         );
+        for warning in &warnings {
+            print!("\n{}{}", termion::clear::CurrentLine, warning);
+            line += 1;
+        }
         for _ in line..nlines {
             print!("\n{}", termion::clear::CurrentLine);
         }
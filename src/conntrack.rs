@@ -0,0 +1,77 @@
+use crate::ct;
+use crate::netlink;
+
+const NFNL_SUBSYS_CTNETLINK: u8 = 1;
+
+const IPCTNL_MSG_CT_GET_STATS_CPU: u8 = 4;
+const IPCTNL_MSG_CT_GET_STATS: u8 = 5;
+
+const NFPROTO_UNSPEC: u8 = 0;
+
+// Per-CPU statistics, CTA_STATS_*. The ones not listed are unused since 4.x and always zero.
+static CPU_STATS: [(u16, &str); 9] = [
+    (2, "found"),
+    (4, "invalid"),
+    (9, "insert_failed"),
+    (10, "drop"),
+    (11, "early_drop"),
+    (12, "error"),
+    (13, "search_restart"),
+    (14, "clash_resolve"),
+    (15, "chain_toolong"),
+];
+
+// Global statistics, CTA_STATS_GLOBAL_*.
+static GLOBAL_STATS: [(u16, &str); 2] = [(1, "entries"), (2, "max_entries")];
+
+#[derive(Debug)]
+pub struct ConntrackStat {
+    pub cpu: Option<u32>,
+    pub name: String,
+    pub value: u64,
+    pub default_unit: ct::UnitChain,
+}
+
+// Per-CPU counters, followed by the global entry count gauges, which have no CPU.
+pub fn stats() -> Result<Vec<ConntrackStat>, String> {
+    let mut stats = Vec::new();
+
+    // The CPU number is passed in the nfgenmsg res_id.
+    for msg in netlink::nfnl_dump(
+        NFNL_SUBSYS_CTNETLINK,
+        IPCTNL_MSG_CT_GET_STATS_CPU,
+        NFPROTO_UNSPEC,
+    )? {
+        let attrs = netlink::nla_attrs(&msg.attrs);
+        for (nla_type, name) in CPU_STATS.iter() {
+            if let Some(value) = netlink::nla_find(&attrs, *nla_type).and_then(netlink::nla_be32) {
+                stats.push(ConntrackStat {
+                    cpu: Some(msg.res_id as u32),
+                    name: name.to_string(),
+                    value: value as u64,
+                    default_unit: ct::unit_units_ps(),
+                });
+            }
+        }
+    }
+
+    for msg in netlink::nfnl_get(
+        NFNL_SUBSYS_CTNETLINK,
+        IPCTNL_MSG_CT_GET_STATS,
+        NFPROTO_UNSPEC,
+    )? {
+        let attrs = netlink::nla_attrs(&msg.attrs);
+        for (nla_type, name) in GLOBAL_STATS.iter() {
+            if let Some(value) = netlink::nla_find(&attrs, *nla_type).and_then(netlink::nla_be32) {
+                stats.push(ConntrackStat {
+                    cpu: None,
+                    name: name.to_string(),
+                    value: value as u64,
+                    default_unit: ct::unit_units(),
+                });
+            }
+        }
+    }
+
+    Ok(stats)
+}
@@ -14,14 +14,25 @@ pub enum UBase {
     Seconds,
     Bits,
     Bytes,
+    Percent,
+    PercentTime,
+    Ppm,
+    Quanta,
+    // Stands for the counter's default unit, see UnitChain::resolve.
+    Default,
 }
 
-static UNITS: [(UBase, char); 5] = [
-    (UBase::Units, '1'),
-    (UBase::Packets, 'p'),
-    (UBase::Seconds, 's'),
-    (UBase::Bits, 'b'),
-    (UBase::Bytes, 'B'),
+static UNITS: [(UBase, &str); 10] = [
+    (UBase::Units, "1"),
+    (UBase::Packets, "p"),
+    (UBase::Seconds, "s"),
+    (UBase::Bits, "b"),
+    (UBase::Bytes, "B"),
+    (UBase::Percent, "%"),
+    (UBase::PercentTime, "%time"),
+    (UBase::Ppm, "ppm"),
+    (UBase::Quanta, "quanta"),
+    (UBase::Default, "x"),
 ];
 
 impl std::string::ToString for UBase {
@@ -35,6 +46,16 @@ impl std::string::ToString for UBase {
     }
 }
 
+impl UBase {
+    // Ratios are shown as they are, without scaling to a prefix.
+    pub fn is_ratio(self) -> bool {
+        match self {
+            UBase::Percent | UBase::PercentTime | UBase::Ppm => true,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
 pub enum UPfx {
     Nano,
@@ -47,21 +68,69 @@ pub enum UPfx {
     Tera,
     Peta,
     Exa,
+    Kibi,
+    Mebi,
+    Gibi,
+    Tebi,
 }
 
-static PREFIXES: [(UPfx, char); 10] = [
-    (UPfx::Nano, 'n'),
-    (UPfx::Micro, 'u'),
-    (UPfx::Milli, 'm'),
-    (UPfx::None, ' '),
-    (UPfx::Kilo, 'K'),
-    (UPfx::Mega, 'M'),
-    (UPfx::Giga, 'G'),
-    (UPfx::Tera, 'T'),
-    (UPfx::Peta, 'P'),
-    (UPfx::Exa, 'E'),
+static PREFIXES: [(UPfx, &str); 10] = [
+    (UPfx::Nano, "n"),
+    (UPfx::Micro, "u"),
+    (UPfx::Milli, "m"),
+    (UPfx::None, " "),
+    (UPfx::Kilo, "K"),
+    (UPfx::Mega, "M"),
+    (UPfx::Giga, "G"),
+    (UPfx::Tera, "T"),
+    (UPfx::Peta, "P"),
+    (UPfx::Exa, "E"),
 ];
 
+static BIN_PREFIXES: [(UPfx, &str); 5] = [
+    (UPfx::None, " "),
+    (UPfx::Kibi, "Ki"),
+    (UPfx::Mebi, "Mi"),
+    (UPfx::Gibi, "Gi"),
+    (UPfx::Tebi, "Ti"),
+];
+
+impl UPfx {
+    pub fn is_binary(self) -> bool {
+        match self {
+            UPfx::Kibi | UPfx::Mebi | UPfx::Gibi | UPfx::Tebi => true,
+            _ => false,
+        }
+    }
+
+    // The table that the prefix is in, and the step between its neighbors.
+    fn table(self) -> (&'static [(UPfx, &'static str)], i128) {
+        if self.is_binary() {
+            (&BIN_PREFIXES, 1024)
+        } else {
+            (&PREFIXES, 1000)
+        }
+    }
+
+    // The multiplier that the prefix stands for.
+    pub fn scale(self) -> Value {
+        let (table, step) = self.table();
+        let none = table
+            .iter()
+            .position(|(pfx, _)| *pfx == UPfx::None)
+            .unwrap();
+        let pos = table.iter().position(|(pfx, _)| *pfx == self).unwrap();
+        let mut scale = Value::from_num(1);
+        for _ in none..pos {
+            scale *= step;
+        }
+        for _ in pos..none {
+            scale /= step;
+        }
+        scale
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Unit {
     pub prefix: UPfx,
@@ -73,12 +142,65 @@ pub enum UFreq {
     AsIs,
     Delta,
     PerSecond,
+    // The counter is a rate already, e.g. an estimate in bytes per second. Shown as it is, per
+    // second.
+    Rate,
 }
 
+// Values are shown with a prefix that fits them, starting at the display prefix. With
+// `fixed_prefix`, the display prefix is always used. `speed` is the link speed in bits per second,
+// needed to convert pause quanta to time. `limit` is what a value is shown as a share of.
 #[derive(Clone, Debug)]
 pub struct UnitChain {
     pub units: Vec<Unit>,
     pub freq: UFreq,
+    pub fixed_prefix: bool,
+    pub speed: Option<Value>,
+    pub limit: Option<Value>,
+}
+
+impl UnitChain {
+    // Whether the two chains start at the same kind of quantity, i.e. whether a value with one
+    // default unit can be shown in the other chain.
+    pub fn same_dimension(&self, other: &UnitChain) -> bool {
+        let dimension = |uchain: &UnitChain| match uchain.units.first().map(|unit| unit.base) {
+            Some(UBase::Bits) => Some(UBase::Bytes),
+            base => base,
+        };
+        dimension(self) == dimension(other)
+    }
+
+    // A chain of the default unit placeholder is the counter's default chain, only shown at the
+    // placeholder's frequency. Per second of a counter that is a rate already is the rate itself.
+    pub fn resolve(&self, default: &UnitChain) -> UnitChain {
+        let freq = match (&self.freq, &default.freq) {
+            (UFreq::PerSecond, UFreq::Rate) => UFreq::Rate,
+            (freq, _) => freq.clone(),
+        };
+        match self.units.first() {
+            Some(unit) if unit.base == UBase::Default => UnitChain {
+                freq: freq,
+                ..default.clone()
+            },
+            _ => UnitChain {
+                freq: freq,
+                ..self.clone()
+            },
+        }
+    }
+}
+
+pub fn unit_units() -> UnitChain {
+    UnitChain {
+        units: vec![Unit {
+            prefix: UPfx::None,
+            base: UBase::Units,
+        }],
+        freq: UFreq::AsIs,
+        fixed_prefix: false,
+        speed: None,
+        limit: None,
+    }
 }
 
 pub fn unit_units_ps() -> UnitChain {
@@ -88,6 +210,35 @@ pub fn unit_units_ps() -> UnitChain {
             base: UBase::Units,
         }],
         freq: UFreq::PerSecond,
+        fixed_prefix: false,
+        speed: None,
+        limit: None,
+    }
+}
+
+pub fn unit_micro_seconds() -> UnitChain {
+    UnitChain {
+        units: vec![Unit {
+            prefix: UPfx::Micro,
+            base: UBase::Seconds,
+        }],
+        freq: UFreq::AsIs,
+        fixed_prefix: false,
+        speed: None,
+        limit: None,
+    }
+}
+
+pub fn unit_nano_seconds() -> UnitChain {
+    UnitChain {
+        units: vec![Unit {
+            prefix: UPfx::Nano,
+            base: UBase::Seconds,
+        }],
+        freq: UFreq::AsIs,
+        fixed_prefix: false,
+        speed: None,
+        limit: None,
     }
 }
 
@@ -99,6 +250,9 @@ pub fn unit_bytes() -> UnitChain {
         }]
         .to_vec(),
         freq: UFreq::AsIs,
+        fixed_prefix: false,
+        speed: None,
+        limit: None,
     }
 }
 
@@ -116,6 +270,29 @@ pub fn unit_bytes_bits_ps() -> UnitChain {
         ]
         .to_vec(),
         freq: UFreq::PerSecond,
+        fixed_prefix: false,
+        speed: None,
+        limit: None,
+    }
+}
+
+pub fn unit_bytes_bits_rate() -> UnitChain {
+    UnitChain {
+        freq: UFreq::Rate,
+        ..unit_bytes_bits_ps()
+    }
+}
+
+pub fn unit_packets() -> UnitChain {
+    UnitChain {
+        units: vec![Unit {
+            prefix: UPfx::None,
+            base: UBase::Packets,
+        }],
+        freq: UFreq::AsIs,
+        fixed_prefix: false,
+        speed: None,
+        limit: None,
     }
 }
 
@@ -127,21 +304,27 @@ pub fn unit_packets_ps() -> UnitChain {
         }]
         .to_vec(),
         freq: UFreq::PerSecond,
+        fixed_prefix: false,
+        speed: None,
+        limit: None,
     }
 }
 
 pub fn humanize(
     mut value: Value,
     base: UPfx,
+    fixed: bool,
     unit_prefix_str: &str,
     unit_str: &String,
     always_decimal: bool,
 ) -> String {
-    let mut pos = PREFIXES.iter().position(|(unit, _)| *unit == base).unwrap();
-    let mut trivial = !always_decimal;
+    let (table, step) = base.table();
+    let mut pos = table.iter().position(|(unit, _)| *unit == base).unwrap();
+    // A converted value can have a fraction even if the counter doesn't.
+    let mut trivial = !always_decimal && value.frac() == 0;
 
-    while value.abs() >= 1100 && (pos + 1) < PREFIXES.len() {
-        value /= 1000;
+    while !fixed && value.abs() >= step + step / 10 && (pos + 1) < table.len() {
+        value /= step;
         pos += 1;
         trivial = false;
     }
@@ -149,56 +332,181 @@ pub fn humanize(
     if trivial {
         format!(
             "{}{:.0}    {}{}",
-            unit_prefix_str, value, PREFIXES[pos].1, &unit_str
+            unit_prefix_str, value, table[pos].1, &unit_str
         )
     } else {
         format!(
             "{}{:.2} {}{}",
-            unit_prefix_str, value, PREFIXES[pos].1, &unit_str
+            unit_prefix_str, value, table[pos].1, &unit_str
         )
     }
 }
 
+// Value and avg are passed after conversion to `unit`. Filters that look at how the counter
+// developed over time get its history, and histories of all counters that are shown.
 pub trait CounterValueFilter: std::fmt::Debug {
-    fn filter(&self, value: &Option<Value>, avg: &Option<Value>) -> bool;
+    fn filter(
+        &self,
+        value: &Option<Value>,
+        avg: &Option<Value>,
+        unit: &Unit,
+        history: &CounterHistory,
+        state: &[&CounterHistory],
+    ) -> bool;
     fn clone_box(&self) -> Box<dyn CounterValueFilter>;
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash)]
 pub enum KeyHead {
     Ifname,
+    Dev,
+    Proto,
+    Irq,
+    Cpu,
     Parent,
     Handle,
     Kind,
+    Vq,
+    Group,
+    Trap,
+    Policer,
+    Resource,
+    Family,
+    Table,
+    Chain,
+    Rule,
+    Object,
+    Local,
+    Remote,
+    Pool,
+    Port,
+    Command,
+    // Columns with user-defined content, such as glob captures of the file namespace.
+    Capture(usize),
     Name,
 }
-pub const ALL_HEADS: [KeyHead; 5] = [
+pub const ALL_HEADS: [KeyHead; 33] = [
     KeyHead::Ifname,
+    KeyHead::Dev,
+    KeyHead::Proto,
+    KeyHead::Irq,
+    KeyHead::Cpu,
     KeyHead::Parent,
     KeyHead::Handle,
     KeyHead::Kind,
+    KeyHead::Vq,
+    KeyHead::Group,
+    KeyHead::Trap,
+    KeyHead::Policer,
+    KeyHead::Resource,
+    KeyHead::Family,
+    KeyHead::Table,
+    KeyHead::Chain,
+    KeyHead::Rule,
+    KeyHead::Object,
+    KeyHead::Local,
+    KeyHead::Remote,
+    KeyHead::Pool,
+    KeyHead::Port,
+    KeyHead::Command,
+    KeyHead::Capture(0),
+    KeyHead::Capture(1),
+    KeyHead::Capture(2),
+    KeyHead::Capture(3),
+    KeyHead::Capture(4),
+    KeyHead::Capture(5),
+    KeyHead::Capture(6),
+    KeyHead::Capture(7),
+    KeyHead::Capture(8),
     KeyHead::Name,
 ];
 
+static CAPTURE_HEADS: [&str; 9] = ["$1", "$2", "$3", "$4", "$5", "$6", "$7", "$8", "$9"];
+pub const MAX_CAPTURES: usize = 9;
+
 impl KeyHead {
     pub fn separate(self) -> bool {
         match self {
-            KeyHead::Ifname | KeyHead::Parent | KeyHead::Name => true,
-            KeyHead::Handle | KeyHead::Kind => false,
+            KeyHead::Ifname
+            | KeyHead::Dev
+            | KeyHead::Proto
+            | KeyHead::Irq
+            | KeyHead::Cpu
+            | KeyHead::Parent
+            | KeyHead::Group
+            | KeyHead::Trap
+            | KeyHead::Policer
+            | KeyHead::Resource
+            | KeyHead::Family
+            | KeyHead::Table
+            | KeyHead::Chain
+            | KeyHead::Rule
+            | KeyHead::Object
+            | KeyHead::Local
+            | KeyHead::Remote
+            | KeyHead::Pool
+            | KeyHead::Port
+            | KeyHead::Command
+            | KeyHead::Capture(_)
+            | KeyHead::Name => true,
+            KeyHead::Handle | KeyHead::Kind | KeyHead::Vq => false,
         }
     }
     pub fn suppress_dups(self) -> bool {
         match self {
-            KeyHead::Ifname | KeyHead::Parent | KeyHead::Handle | KeyHead::Kind => true,
+            KeyHead::Ifname
+            | KeyHead::Dev
+            | KeyHead::Proto
+            | KeyHead::Irq
+            | KeyHead::Cpu
+            | KeyHead::Parent
+            | KeyHead::Handle
+            | KeyHead::Kind
+            | KeyHead::Vq
+            | KeyHead::Group
+            | KeyHead::Trap
+            | KeyHead::Policer
+            | KeyHead::Resource
+            | KeyHead::Family
+            | KeyHead::Table
+            | KeyHead::Chain
+            | KeyHead::Rule
+            | KeyHead::Object
+            | KeyHead::Local
+            | KeyHead::Remote
+            | KeyHead::Pool
+            | KeyHead::Port
+            | KeyHead::Command
+            | KeyHead::Capture(_) => true,
             KeyHead::Name => false,
         }
     }
     pub fn column_head(self) -> &'static str {
         match self {
             KeyHead::Ifname => "if",
+            KeyHead::Dev => "dev",
+            KeyHead::Proto => "proto",
+            KeyHead::Irq => "irq",
+            KeyHead::Cpu => "cpu",
             KeyHead::Parent => "par",
             KeyHead::Handle => "hnd",
             KeyHead::Kind => "kind",
+            KeyHead::Vq => "vq",
+            KeyHead::Group => "group",
+            KeyHead::Trap => "trap",
+            KeyHead::Policer => "pol",
+            KeyHead::Resource => "resource",
+            KeyHead::Family => "fam",
+            KeyHead::Table => "table",
+            KeyHead::Chain => "chain",
+            KeyHead::Rule => "rule",
+            KeyHead::Object => "obj",
+            KeyHead::Local => "local",
+            KeyHead::Remote => "remote",
+            KeyHead::Pool => "pool",
+            KeyHead::Port => "port",
+            KeyHead::Command => "cmd",
+            KeyHead::Capture(n) => CAPTURE_HEADS[n],
             KeyHead::Name => "counter",
         }
     }
@@ -219,6 +527,103 @@ pub struct CounterImm {
 
 pub trait CounterRule: std::fmt::Debug {
     fn counters(&self) -> Result<Vec<CounterImm>, String>;
+
+    // Names of all counters that the rule could show, whether they match or not. Used to point
+    // out counter names that look mistyped.
+    fn names(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    // Counter names that the rule's counter matches give literally, rather than as globs or
+    // regexes. These are checked against names().
+    fn plain_names(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+// Values of a counter over time. `history` holds values from past cycles, oldest first, which are
+// followed by `prev` and `curr`.
+pub struct CounterHistory {
+    pub key: CounterKey,
+    pub history: Vec<u64>,
+    pub curr: Option<u64>,
+    pub prev: Option<u64>,
+    pub base: u64,
+    pub changed: bool,
+    pub age: u32,
+    pub unit: UnitChain,
+    pub filter: Vec<Box<dyn CounterValueFilter>>,
+}
+
+impl CounterHistory {
+    // Whether the counter had different values within the history window.
+    pub fn moved(&self) -> bool {
+        self.history
+            .iter()
+            .chain(self.prev.iter())
+            .any(|&value| Some(value) != self.curr)
+    }
+
+    // Increment in the last cycle, and average increment per cycle over the history window.
+    // Decrements count as zero.
+    pub fn deltas(&self) -> Option<(Value, Value)> {
+        let curr = self.curr?;
+        let prev = self.prev?;
+        let first = *self.history.first()?;
+        let last = Value::from_num(curr.saturating_sub(prev));
+        let avg =
+            Value::from_num(curr.saturating_sub(first)) / Value::from_num(self.history.len() + 1);
+        Some((last, avg))
+    }
+
+    // The counter in the same place as this one, that counts in the opposite direction, e.g.
+    // rx_packets for tx_packets. `state` should hold all selected counters, not just the displayed
+    // ones; counters that no rule selected are not fetched, so have no sibling.
+    pub fn sibling<'a>(&self, state: &[&'a CounterHistory]) -> Option<&'a CounterHistory> {
+        state.iter().cloned().find(|other| {
+            other.key.ctns == self.key.ctns
+                && other.key.key.len() == self.key.key.len()
+                && other.key.key.iter().zip(self.key.key.iter()).all(
+                    |((head, value), (self_head, self_value))| {
+                        head == self_head
+                            && match head {
+                                KeyHead::Name => Some(value) == swap_direction(self_value).as_ref(),
+                                _ => value == self_value,
+                            }
+                    },
+                )
+        })
+    }
+}
+
+// Swap "tx" name tokens for "rx" and vice versa, e.g. rx_queue_0_packets for tx_queue_0_packets.
+// Only whole tokens are swapped, so that e.g. ctx_switches has no sibling.
+fn swap_direction(name: &str) -> Option<String> {
+    let mut ret = String::new();
+    let mut swapped = false;
+    let mut rest = name;
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(rest.len());
+        let token = match &rest[..end] {
+            "tx" => "rx",
+            "rx" => "tx",
+            token => token,
+        };
+        swapped |= token != &rest[..end];
+        ret.push_str(token);
+        let next = rest[end..]
+            .find(|c: char| c.is_ascii_alphanumeric())
+            .map_or(rest.len(), |i| end + i);
+        ret.push_str(&rest[end..next]);
+        rest = &rest[next..];
+    }
+    if swapped {
+        Some(ret)
+    } else {
+        None
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -231,7 +636,14 @@ impl NonZeroCounterFilter {
 }
 
 impl CounterValueFilter for NonZeroCounterFilter {
-    fn filter(&self, value: &Option<Value>, avg: &Option<Value>) -> bool {
+    fn filter(
+        &self,
+        value: &Option<Value>,
+        avg: &Option<Value>,
+        _unit: &Unit,
+        _history: &CounterHistory,
+        _state: &[&CounterHistory],
+    ) -> bool {
         self.do_filter(&value, &avg)
     }
     fn clone_box(&self) -> Box<dyn CounterValueFilter> {
@@ -239,6 +651,201 @@ impl CounterValueFilter for NonZeroCounterFilter {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThresholdOp {
+    Lt,
+    Le,
+    Eq,
+    Ge,
+    Gt,
+}
+
+// Compares the value, or the average, against a threshold. The threshold is kept without a
+// prefix, and the counter is scaled to match. If the threshold was given with a unit, counters
+// that can't be converted to it don't pass.
+#[derive(Debug, Clone)]
+pub struct ThresholdCounterFilter {
+    pub avg: bool,
+    pub op: ThresholdOp,
+    pub threshold: Value,
+    pub base: Option<UBase>,
+    // Set if the threshold is per second or a delta. It then only applies to counters shown so.
+    pub freq: Option<UFreq>,
+}
+
+impl CounterValueFilter for ThresholdCounterFilter {
+    fn filter(
+        &self,
+        value: &Option<Value>,
+        avg: &Option<Value>,
+        unit: &Unit,
+        history: &CounterHistory,
+        _state: &[&CounterHistory],
+    ) -> bool {
+        // A rate is shown per second.
+        let shown_freq = match history.unit.freq {
+            UFreq::Rate => UFreq::PerSecond,
+            ref freq => freq.clone(),
+        };
+        match &self.freq {
+            Some(freq) if *freq != shown_freq => return false,
+            _ => {}
+        }
+        let value = match if self.avg { avg } else { value } {
+            Some(value) => value.saturating_mul(unit.prefix.scale()),
+            None => return false,
+        };
+        // Only compare within a dimension, e.g. bytes against a threshold in bits.
+        let value = match self.base {
+            None => value,
+            Some(base) if base == unit.base => value,
+            Some(base) => match CONVERSIONS
+                .iter()
+                .find(|(from, to, _)| *from == unit.base && *to == base)
+            {
+                Some((_, _, Conversion::Ratio(mul, div))) => {
+                    value.saturating_mul(Value::from_num(*mul)) / Value::from_num(*div)
+                }
+                _ => return false,
+            },
+        };
+        match self.op {
+            ThresholdOp::Lt => value < self.threshold,
+            ThresholdOp::Le => value <= self.threshold,
+            ThresholdOp::Eq => value == self.threshold,
+            ThresholdOp::Ge => value >= self.threshold,
+            ThresholdOp::Gt => value > self.threshold,
+        }
+    }
+    fn clone_box(&self) -> Box<dyn CounterValueFilter> {
+        Box::new(self.clone())
+    }
+}
+
+// Counters that had a different value at some point since they were first seen.
+#[derive(Debug, Clone)]
+pub struct ChangedCounterFilter {}
+
+impl CounterValueFilter for ChangedCounterFilter {
+    fn filter(
+        &self,
+        _value: &Option<Value>,
+        _avg: &Option<Value>,
+        _unit: &Unit,
+        history: &CounterHistory,
+        _state: &[&CounterHistory],
+    ) -> bool {
+        history.changed
+    }
+    fn clone_box(&self) -> Box<dyn CounterValueFilter> {
+        Box::new(self.clone())
+    }
+}
+
+// Counters that did not move over the averaging window, while their sibling did. The window needs
+// to be full, which is when the average is available.
+#[derive(Debug, Clone)]
+pub struct StuckCounterFilter {}
+
+impl CounterValueFilter for StuckCounterFilter {
+    fn filter(
+        &self,
+        _value: &Option<Value>,
+        avg: &Option<Value>,
+        _unit: &Unit,
+        history: &CounterHistory,
+        state: &[&CounterHistory],
+    ) -> bool {
+        avg.is_some()
+            && !history.moved()
+            && history
+                .sibling(state)
+                .map_or(false, |sibling| sibling.moved())
+    }
+    fn clone_box(&self) -> Box<dyn CounterValueFilter> {
+        Box::new(self.clone())
+    }
+}
+
+// Counters whose last increment is more than `factor` times the average increment over the
+// window.
+#[derive(Debug, Clone)]
+pub struct SpikeCounterFilter {
+    pub factor: Value,
+}
+
+impl CounterValueFilter for SpikeCounterFilter {
+    fn filter(
+        &self,
+        _value: &Option<Value>,
+        _avg: &Option<Value>,
+        _unit: &Unit,
+        history: &CounterHistory,
+        _state: &[&CounterHistory],
+    ) -> bool {
+        match history.deltas() {
+            Some((last, avg)) => last > avg.saturating_mul(self.factor),
+            None => false,
+        }
+    }
+    fn clone_box(&self) -> Box<dyn CounterValueFilter> {
+        Box::new(self.clone())
+    }
+}
+
+// How a value in one unit is converted to the next unit of a chain.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Conversion {
+    // Multiply by the ratio. The prefix carries over, e.g. KB converts to Kb.
+    Ratio(i128, i128),
+    // Pause quanta are 512 bit times, which depends on the link speed. The result is in seconds.
+    QuantaTime,
+    // Time spent per second, as a share of that second, multiplied by the ratio's scale.
+    TimeShare(i128),
+    // Share of the chain's limit, multiplied by the ratio's scale.
+    Share(i128),
+}
+
+// (from, to, conversion). Anything can be shown as plain units and vice versa, and any unit as
+// itself.
+static CONVERSIONS: [(UBase, UBase, Conversion); 10] = [
+    (UBase::Bytes, UBase::Bits, Conversion::Ratio(8, 1)),
+    (UBase::Bits, UBase::Bytes, Conversion::Ratio(1, 8)),
+    (UBase::Quanta, UBase::Bits, Conversion::Ratio(512, 1)),
+    (UBase::Bits, UBase::Quanta, Conversion::Ratio(1, 512)),
+    (UBase::Quanta, UBase::Seconds, Conversion::QuantaTime),
+    (UBase::Seconds, UBase::Percent, Conversion::TimeShare(100)),
+    (
+        UBase::Seconds,
+        UBase::PercentTime,
+        Conversion::TimeShare(100),
+    ),
+    (UBase::Seconds, UBase::Ppm, Conversion::TimeShare(1_000_000)),
+    (UBase::Percent, UBase::Ppm, Conversion::Ratio(10_000, 1)),
+    (UBase::Ppm, UBase::Percent, Conversion::Ratio(1, 10_000)),
+];
+
+// With a limit, any quantity can be shown as a ratio of it.
+pub fn conversion(from: UBase, to: UBase, limit: Option<Value>) -> Option<Conversion> {
+    if limit.is_some() && to.is_ratio() && !from.is_ratio() {
+        return Some(Conversion::Share(match to {
+            UBase::Ppm => 1_000_000,
+            _ => 100,
+        }));
+    }
+    if from == to || from == UBase::Units || to == UBase::Units {
+        return Some(Conversion::Ratio(1, 1));
+    }
+    CONVERSIONS
+        .iter()
+        .find(|(f, t, _)| *f == from && *t == to)
+        .map(|(_, _, conv)| *conv)
+}
+
+// The first unit of the chain is what the counter is counted in. The following ones convert it to
+// other units. A prefix given in a later unit is the prefix to show the value in, otherwise it is
+// shown in the prefix of the first unit. A fixed prefix is taken from the last unit even if it
+// has none.
 pub fn convert(
     uchain: &UnitChain,
     value: Option<Value>,
@@ -248,21 +855,44 @@ pub fn convert(
 
     let mut it = uchain.units.iter();
     let mut prev_unit = it.next().unwrap();
-    let ret_prefix = prev_unit.prefix;
+    let mut ret_prefix = prev_unit.prefix;
     let mut ret_value = value;
     let mut ret_avg = avg;
 
-    for unit in it {
-        match (prev_unit.base, unit.base) {
-            (UBase::Bytes, UBase::Bits) => {
-                ret_value = ret_value.map(|v| v * 8);
-                ret_avg = ret_avg.map(|av| av * 8);
+    for (i, unit) in it.enumerate() {
+        let last = i + 2 == uchain.units.len();
+
+        // Conversions between dimensions are done on unprefixed values.
+        let (mul, div) = match conversion(prev_unit.base, unit.base, uchain.limit) {
+            Some(Conversion::Ratio(mul, div)) => (Value::from_num(mul), Value::from_num(div)),
+            Some(Conversion::QuantaTime) => {
+                let speed = uchain.speed.unwrap_or(Value::from_num(1));
+                let mul = ret_prefix.scale().saturating_mul(Value::from_num(512));
+                ret_prefix = UPfx::None;
+                (mul, speed)
             }
-            (UBase::Bits, UBase::Bytes) => {
-                ret_value = ret_value.map(|v| v / 8);
-                ret_avg = avg.map(|av| av / 8);
+            Some(Conversion::TimeShare(scale)) => {
+                let mul = ret_prefix.scale().saturating_mul(Value::from_num(scale));
+                ret_prefix = UPfx::None;
+                (mul, Value::from_num(1))
             }
-            _ => {}
+            Some(Conversion::Share(scale)) => {
+                let limit = uchain.limit.unwrap_or(Value::from_num(1));
+                let mul = ret_prefix.scale().saturating_mul(Value::from_num(scale));
+                ret_prefix = UPfx::None;
+                (mul, limit)
+            }
+            None => (Value::from_num(1), Value::from_num(1)),
+        };
+        ret_value = ret_value.map(|v| v.saturating_mul(mul) / div);
+        ret_avg = ret_avg.map(|av| av.saturating_mul(mul) / div);
+
+        if (unit.prefix != UPfx::None || (last && uchain.fixed_prefix)) && unit.prefix != ret_prefix
+        {
+            let (from, to) = (ret_prefix.scale(), unit.prefix.scale());
+            ret_value = ret_value.map(|v| v.saturating_mul(from) / to);
+            ret_avg = ret_avg.map(|av| av.saturating_mul(from) / to);
+            ret_prefix = unit.prefix;
         }
         prev_unit = unit;
     }
@@ -276,3 +906,185 @@ pub fn convert(
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_rate() {
+        // Bytes per second of a rate is the rate itself.
+        let mut bps = unit_bytes_bits_ps();
+        assert_eq!(bps.resolve(&unit_bytes_bits_rate()).freq, UFreq::Rate);
+        assert_eq!(bps.resolve(&unit_bytes()).freq, UFreq::PerSecond);
+        bps.units = vec![Unit {
+            prefix: UPfx::None,
+            base: UBase::Default,
+        }];
+        let resolved = bps.resolve(&unit_bytes_bits_rate());
+        assert_eq!(resolved.freq, UFreq::Rate);
+        assert_eq!(resolved.units[1].base, UBase::Bits);
+        // A delta is still a delta.
+        let mut delta = unit_bytes();
+        delta.freq = UFreq::Delta;
+        assert_eq!(delta.resolve(&unit_bytes_bits_rate()).freq, UFreq::Delta);
+    }
+
+    fn chain(units: &[(UPfx, UBase)], fixed_prefix: bool, limit: Option<u64>) -> UnitChain {
+        UnitChain {
+            units: units
+                .iter()
+                .map(|(prefix, base)| Unit {
+                    prefix: *prefix,
+                    base: *base,
+                })
+                .collect(),
+            freq: UFreq::AsIs,
+            fixed_prefix: fixed_prefix,
+            speed: None,
+            limit: limit.map(Value::from_num),
+        }
+    }
+
+    fn check(uchain: &UnitChain, value: u64, expected: u64, prefix: UPfx, base: UBase) {
+        let (ret, avg, unit) = convert(uchain, Some(Value::from_num(value)), None);
+        assert_eq!(ret, Some(Value::from_num(expected)));
+        assert_eq!(avg, None);
+        assert_eq!(unit.prefix, prefix);
+        assert_eq!(unit.base, base);
+    }
+
+    #[test]
+    fn convert_bytes_bits() {
+        let uchain = chain(
+            &[(UPfx::None, UBase::Bytes), (UPfx::None, UBase::Bits)],
+            false,
+            None,
+        );
+        check(&uchain, 1000, 8000, UPfx::None, UBase::Bits);
+        // The prefix of the first unit carries over.
+        let uchain = chain(
+            &[(UPfx::Kilo, UBase::Bytes), (UPfx::None, UBase::Bits)],
+            false,
+            None,
+        );
+        check(&uchain, 3, 24, UPfx::Kilo, UBase::Bits);
+    }
+
+    #[test]
+    fn convert_binary_prefix() {
+        // /B/MiB and back with /MiB/=B.
+        let uchain = chain(
+            &[(UPfx::None, UBase::Bytes), (UPfx::Mebi, UBase::Bytes)],
+            false,
+            None,
+        );
+        check(&uchain, 3 << 20, 3, UPfx::Mebi, UBase::Bytes);
+        let uchain = chain(
+            &[(UPfx::Mebi, UBase::Bytes), (UPfx::None, UBase::Bytes)],
+            true,
+            None,
+        );
+        check(&uchain, 3, 3 << 20, UPfx::None, UBase::Bytes);
+        // Without a fixed prefix, the value stays in the first unit's prefix.
+        let uchain = chain(
+            &[(UPfx::Kibi, UBase::Bytes), (UPfx::None, UBase::Bytes)],
+            false,
+            None,
+        );
+        check(&uchain, 2, 2, UPfx::Kibi, UBase::Bytes);
+        // /Kib/KiB
+        let uchain = chain(
+            &[(UPfx::Kibi, UBase::Bits), (UPfx::Kibi, UBase::Bytes)],
+            false,
+            None,
+        );
+        check(&uchain, 16, 2, UPfx::Kibi, UBase::Bytes);
+    }
+
+    #[test]
+    fn convert_share_of_limit() {
+        // /B/%of1M
+        let uchain = chain(
+            &[(UPfx::None, UBase::Bytes), (UPfx::None, UBase::Percent)],
+            false,
+            Some(1_000_000),
+        );
+        check(&uchain, 250_000, 25, UPfx::None, UBase::Percent);
+        // /KB/ppmof1M, the limit is in the unprefixed unit.
+        let uchain = chain(
+            &[(UPfx::Kilo, UBase::Bytes), (UPfx::None, UBase::Ppm)],
+            false,
+            Some(1_000_000),
+        );
+        check(&uchain, 250, 250_000, UPfx::None, UBase::Ppm);
+        // Without a limit, % is just a ratio of the value.
+        let uchain = chain(
+            &[(UPfx::None, UBase::Units), (UPfx::None, UBase::Percent)],
+            false,
+            None,
+        );
+        check(&uchain, 7, 7, UPfx::None, UBase::Percent);
+    }
+
+    fn history(freq: UFreq) -> CounterHistory {
+        CounterHistory {
+            key: CounterKey {
+                ctns: "link",
+                key: vec![(KeyHead::Name, "rx_bytes".to_string())],
+            },
+            history: vec![],
+            curr: Some(0),
+            prev: None,
+            base: 0,
+            changed: false,
+            age: 0,
+            unit: UnitChain {
+                freq: freq,
+                ..unit_bytes_bits_ps()
+            },
+            filter: vec![],
+        }
+    }
+
+    #[test]
+    fn threshold() {
+        // >1K/bps
+        let threshold = ThresholdCounterFilter {
+            avg: false,
+            op: ThresholdOp::Gt,
+            threshold: Value::from_num(1000),
+            base: Some(UBase::Bits),
+            freq: Some(UFreq::PerSecond),
+        };
+        let bits = Unit {
+            prefix: UPfx::None,
+            base: UBase::Bits,
+        };
+        let bytes = Unit {
+            prefix: UPfx::None,
+            base: UBase::Bytes,
+        };
+        let per_second = history(UFreq::PerSecond);
+        let pass = |value: u64, unit: &Unit, history: &CounterHistory| {
+            threshold.filter(&Some(Value::from_num(value)), &None, unit, history, &[])
+        };
+        assert!(pass(1001, &bits, &per_second));
+        assert!(!pass(1000, &bits, &per_second));
+        // Bytes are compared in bits.
+        assert!(pass(126, &bytes, &per_second));
+        assert!(!pass(125, &bytes, &per_second));
+        // Seconds can't be converted to bits.
+        let seconds = Unit {
+            prefix: UPfx::None,
+            base: UBase::Seconds,
+        };
+        assert!(!pass(2000, &seconds, &per_second));
+        // A per second threshold only applies to rates.
+        assert!(pass(2000, &bits, &history(UFreq::Rate)));
+        assert!(!pass(2000, &bits, &history(UFreq::Delta)));
+        assert!(!pass(2000, &bits, &history(UFreq::AsIs)));
+        // No value yet.
+        assert!(!threshold.filter(&None, &None, &bits, &per_second, &[]));
+    }
+}
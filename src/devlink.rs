@@ -0,0 +1,216 @@
+use crate::ct;
+use crate::netlink;
+
+const DEVLINK_GENL_NAME: &str = "devlink";
+const DEVLINK_GENL_VERSION: u8 = 1;
+
+const DEVLINK_CMD_GET: u8 = 1;
+const DEVLINK_CMD_RESOURCE_DUMP: u8 = 36;
+const DEVLINK_CMD_TRAP_GET: u8 = 61;
+const DEVLINK_CMD_TRAP_GROUP_GET: u8 = 65;
+const DEVLINK_CMD_TRAP_POLICER_GET: u8 = 69;
+
+const DEVLINK_ATTR_BUS_NAME: u16 = 1;
+const DEVLINK_ATTR_DEV_NAME: u16 = 2;
+const DEVLINK_ATTR_RESOURCE_LIST: u16 = 63;
+const DEVLINK_ATTR_RESOURCE: u16 = 64;
+const DEVLINK_ATTR_RESOURCE_NAME: u16 = 65;
+const DEVLINK_ATTR_RESOURCE_SIZE: u16 = 67;
+const DEVLINK_ATTR_RESOURCE_OCC: u16 = 74;
+const DEVLINK_ATTR_STATS: u16 = 129;
+const DEVLINK_ATTR_TRAP_NAME: u16 = 130;
+const DEVLINK_ATTR_TRAP_GROUP_NAME: u16 = 135;
+const DEVLINK_ATTR_TRAP_POLICER_ID: u16 = 142;
+
+// Nested in DEVLINK_ATTR_STATS.
+const DEVLINK_ATTR_STATS_RX_PACKETS: u16 = 0;
+const DEVLINK_ATTR_STATS_RX_BYTES: u16 = 1;
+const DEVLINK_ATTR_STATS_RX_DROPPED: u16 = 2;
+
+#[derive(Debug)]
+pub struct TrapStat {
+    pub dev: String,
+    pub group: Option<String>,
+    pub trap: Option<String>,
+    pub policer: Option<u32>,
+    pub name: String,
+    pub value: u64,
+    pub default_unit: ct::UnitChain,
+}
+
+#[derive(Debug)]
+pub struct ResourceStat {
+    pub dev: String,
+    pub path: String,
+    pub occ: Option<u64>,
+    pub size: u64,
+}
+
+// Devlink instances are identified by bus and device name, e.g. pci/0000:03:00.0.
+pub fn dev_handle(attrs: &[(u16, &[u8])]) -> Option<String> {
+    match (
+        netlink::nla_find(attrs, DEVLINK_ATTR_BUS_NAME),
+        netlink::nla_find(attrs, DEVLINK_ATTR_DEV_NAME),
+    ) {
+        (Some(bus), Some(dev)) => Some(format!(
+            "{}/{}",
+            netlink::nla_string(bus),
+            netlink::nla_string(dev)
+        )),
+        _ => None,
+    }
+}
+
+fn push_trap_stats(
+    stats: &mut Vec<TrapStat>,
+    attrs: &[(u16, &[u8])],
+    group: Option<String>,
+    trap: Option<String>,
+    policer: Option<u32>,
+) -> Result<(), String> {
+    let dev = dev_handle(attrs).ok_or("Devlink trap message without a device handle")?;
+    let nested = match netlink::nla_find(attrs, DEVLINK_ATTR_STATS) {
+        Some(nested) => nested,
+        None => return Ok(()),
+    };
+
+    for (nla_type, payload) in netlink::nla_attrs(nested) {
+        let (name, default_unit) = match nla_type {
+            DEVLINK_ATTR_STATS_RX_PACKETS => ("rx_packets", ct::unit_packets_ps()),
+            DEVLINK_ATTR_STATS_RX_BYTES => ("rx_bytes", ct::unit_bytes_bits_ps()),
+            DEVLINK_ATTR_STATS_RX_DROPPED => ("rx_dropped", ct::unit_packets_ps()),
+            _ => continue,
+        };
+        let value =
+            netlink::nla_u64(payload).ok_or(format!("Unexpected size of devlink stat {}", name))?;
+        stats.push(TrapStat {
+            dev: dev.clone(),
+            group: group.clone(),
+            trap: trap.clone(),
+            policer: policer,
+            name: name.to_string(),
+            value: value,
+            default_unit: default_unit,
+        });
+    }
+    Ok(())
+}
+
+// Statistics of all traps, trap groups and trap policers of all devlink instances.
+pub fn trap_stats() -> Result<Vec<TrapStat>, String> {
+    let mut stats = Vec::new();
+
+    for msg in netlink::genl_dump(
+        DEVLINK_GENL_NAME,
+        DEVLINK_GENL_VERSION,
+        DEVLINK_CMD_TRAP_GET,
+        Vec::new(),
+    )? {
+        let attrs = netlink::nla_attrs(&msg.attrs);
+        let group =
+            netlink::nla_find(&attrs, DEVLINK_ATTR_TRAP_GROUP_NAME).map(netlink::nla_string);
+        let trap = netlink::nla_find(&attrs, DEVLINK_ATTR_TRAP_NAME).map(netlink::nla_string);
+        push_trap_stats(&mut stats, &attrs, group, trap, None)?;
+    }
+
+    for msg in netlink::genl_dump(
+        DEVLINK_GENL_NAME,
+        DEVLINK_GENL_VERSION,
+        DEVLINK_CMD_TRAP_GROUP_GET,
+        Vec::new(),
+    )? {
+        let attrs = netlink::nla_attrs(&msg.attrs);
+        let group =
+            netlink::nla_find(&attrs, DEVLINK_ATTR_TRAP_GROUP_NAME).map(netlink::nla_string);
+        push_trap_stats(&mut stats, &attrs, group, None, None)?;
+    }
+
+    for msg in netlink::genl_dump(
+        DEVLINK_GENL_NAME,
+        DEVLINK_GENL_VERSION,
+        DEVLINK_CMD_TRAP_POLICER_GET,
+        Vec::new(),
+    )? {
+        let attrs = netlink::nla_attrs(&msg.attrs);
+        let policer =
+            netlink::nla_find(&attrs, DEVLINK_ATTR_TRAP_POLICER_ID).and_then(netlink::nla_u32);
+        push_trap_stats(&mut stats, &attrs, None, None, policer)?;
+    }
+
+    Ok(stats)
+}
+
+// Walk a DEVLINK_ATTR_RESOURCE_LIST nest. Resources form a tree, and each is reported under its
+// full path, e.g. /kvd/linear.
+fn push_resource_stats(
+    stats: &mut Vec<ResourceStat>,
+    dev: &String,
+    prefix: &str,
+    list: &[u8],
+) -> Result<(), String> {
+    for (nla_type, nested) in netlink::nla_attrs(list) {
+        if nla_type != DEVLINK_ATTR_RESOURCE {
+            continue;
+        }
+        let attrs = netlink::nla_attrs(nested);
+        let name = netlink::nla_find(&attrs, DEVLINK_ATTR_RESOURCE_NAME)
+            .map(netlink::nla_string)
+            .ok_or("Devlink resource without a name")?;
+        let path = format!("{}/{}", prefix, name);
+        let size = netlink::nla_find(&attrs, DEVLINK_ATTR_RESOURCE_SIZE)
+            .and_then(netlink::nla_u64)
+            .ok_or(format!("Devlink resource {} without a size", path))?;
+        let occ = netlink::nla_find(&attrs, DEVLINK_ATTR_RESOURCE_OCC).and_then(netlink::nla_u64);
+        stats.push(ResourceStat {
+            dev: dev.clone(),
+            path: path.clone(),
+            occ: occ,
+            size: size,
+        });
+        if let Some(children) = netlink::nla_find(&attrs, DEVLINK_ATTR_RESOURCE_LIST) {
+            push_resource_stats(stats, dev, &path, children)?;
+        }
+    }
+    Ok(())
+}
+
+// Sizes and occupancy of resources of all devlink instances.
+pub fn resource_stats() -> Result<Vec<ResourceStat>, String> {
+    let mut stats = Vec::new();
+
+    for msg in netlink::genl_dump(
+        DEVLINK_GENL_NAME,
+        DEVLINK_GENL_VERSION,
+        DEVLINK_CMD_GET,
+        Vec::new(),
+    )? {
+        let attrs = netlink::nla_attrs(&msg.attrs);
+        let dev = match dev_handle(&attrs) {
+            Some(dev) => dev,
+            None => continue,
+        };
+
+        // RESOURCE_DUMP is not a dump in the netlink sense, it needs to be asked for each instance
+        // separately. The payloads of the handle attributes are passed through as they are.
+        let mut req = Vec::new();
+        for nla_type in &[DEVLINK_ATTR_BUS_NAME, DEVLINK_ATTR_DEV_NAME] {
+            if let Some(payload) = netlink::nla_find(&attrs, *nla_type) {
+                netlink::nla_put(&mut req, *nla_type, payload);
+            }
+        }
+
+        for msg in netlink::genl_multi(
+            DEVLINK_GENL_NAME,
+            DEVLINK_GENL_VERSION,
+            DEVLINK_CMD_RESOURCE_DUMP,
+            req,
+        )? {
+            let attrs = netlink::nla_attrs(&msg.attrs);
+            if let Some(list) = netlink::nla_find(&attrs, DEVLINK_ATTR_RESOURCE_LIST) {
+                push_resource_stats(&mut stats, &dev, "", list)?;
+            }
+        }
+    }
+
+    Ok(stats)
+}
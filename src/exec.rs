@@ -0,0 +1,334 @@
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// At most this many commands run at any time, across all exec rules.
+const MAX_RUNNING: usize = 4;
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Debug)]
+pub struct ExecStat {
+    pub name: String,
+    pub value: u64,
+}
+
+#[derive(Debug)]
+struct RunnerState {
+    running: bool,
+    last: Result<Vec<ExecStat>, String>,
+}
+
+// Runs a shell command in the background and keeps the counters from its last completed run. This
+// way a slow command doesn't hold up the other namespaces, it just updates less often.
+#[derive(Debug)]
+pub struct Runner {
+    command: String,
+    timeout: Duration,
+    state: Arc<Mutex<RunnerState>>,
+}
+
+fn run(command: &str, timeout: Duration) -> Result<String, String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("{}: {}", command, e))?;
+
+    // Read the output on the side, so that a command with a lot of output doesn't block on a full
+    // pipe while we wait for it to exit.
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{}: timed out after {:?}", command, timeout));
+            }
+            Err(e) => return Err(format!("{}: {}", command, e)),
+        }
+    };
+
+    let output = reader
+        .join()
+        .unwrap()
+        .map_err(|e| format!("{}: {}", command, e))?;
+    if !status.success() {
+        return Err(format!("{}: {}", command, status));
+    }
+    Ok(output)
+}
+
+// Lines of "name value". Lines that don't have that form are skipped, so that e.g. headers in the
+// output don't need to be filtered out.
+fn parse_lines(output: &str) -> Vec<ExecStat> {
+    let mut stats = Vec::new();
+    for line in output.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() != 2 {
+            continue;
+        }
+        if let Ok(value) = words[1].parse::<u64>() {
+            stats.push(ExecStat {
+                name: words[0].trim_end_matches(':').to_string(),
+                value: value,
+            });
+        }
+    }
+    stats
+}
+
+// A minimal JSON reader. Numbers are flattened into counters named after the path that leads to
+// them, with object keys and array indices joined by '.', e.g. "ports.0.rx_bytes". Values that
+// are not non-negative integers are skipped.
+struct JsonReader<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> JsonReader<'a> {
+    fn skip_ws(&mut self) {
+        while self.pos < self.text.len() && (self.text[self.pos] as char).is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_ws();
+        self.text.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, ch: u8) -> Result<(), String> {
+        if self.peek() == Some(ch) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!(
+                "JSON: expected '{}' at offset {}",
+                ch as char, self.pos
+            ))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut ret = Vec::new();
+        while let Some(&ch) = self.text.get(self.pos) {
+            self.pos += 1;
+            match ch {
+                b'"' => return Ok(String::from_utf8_lossy(&ret).into_owned()),
+                b'\\' => {
+                    // Escapes are kept as the escaped character. Nobody names counters with
+                    // \u sequences.
+                    if let Some(&esc) = self.text.get(self.pos) {
+                        ret.push(esc);
+                        self.pos += 1;
+                    }
+                }
+                _ => ret.push(ch),
+            }
+        }
+        Err("JSON: unterminated string".to_string())
+    }
+
+    fn child_name(prefix: &str, name: &str) -> String {
+        if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", prefix, name)
+        }
+    }
+
+    fn value(&mut self, name: &str, stats: &mut Vec<ExecStat>) -> Result<(), String> {
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                loop {
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    self.value(&Self::child_name(name, &key), stats)?;
+                    if self.peek() == Some(b',') {
+                        self.pos += 1;
+                    } else {
+                        return self.expect(b'}');
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                let mut idx = 0;
+                loop {
+                    self.value(&Self::child_name(name, &idx.to_string()), stats)?;
+                    idx += 1;
+                    if self.peek() == Some(b',') {
+                        self.pos += 1;
+                    } else {
+                        return self.expect(b']');
+                    }
+                }
+            }
+            Some(b'"') => self.string().map(|_| ()),
+            Some(_) => {
+                let start = self.pos;
+                while let Some(&ch) = self.text.get(self.pos) {
+                    if ch == b',' || ch == b'}' || ch == b']' || (ch as char).is_whitespace() {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                let word = String::from_utf8_lossy(&self.text[start..self.pos]);
+                if let Ok(value) = word.parse::<u64>() {
+                    stats.push(ExecStat {
+                        name: name.to_string(),
+                        value: value,
+                    });
+                }
+                Ok(())
+            }
+            None => Err("JSON: unexpected end of input".to_string()),
+        }
+    }
+}
+
+fn parse_json(output: &str) -> Result<Vec<ExecStat>, String> {
+    let mut stats = Vec::new();
+    let mut reader = JsonReader {
+        text: output.as_bytes(),
+        pos: 0,
+    };
+    reader.value("", &mut stats)?;
+    Ok(stats)
+}
+
+pub fn parse_output(output: &str) -> Result<Vec<ExecStat>, String> {
+    match output.trim_start().chars().next() {
+        Some('{') | Some('[') => parse_json(output),
+        _ => Ok(parse_lines(output)),
+    }
+}
+
+impl Runner {
+    pub fn new(command: &str, timeout: Duration) -> Runner {
+        Runner {
+            command: command.to_string(),
+            timeout: timeout,
+            state: Arc::new(Mutex::new(RunnerState {
+                running: false,
+                last: Ok(Vec::new()),
+            })),
+        }
+    }
+
+    // Start a new run unless one is already in progress, or too many commands are running, and
+    // return counters of the last completed run. Nothing is returned until the first run
+    // completes.
+    pub fn poll(&self) -> Result<Vec<ExecStat>, String> {
+        let mut state = self.state.lock().unwrap();
+        if !state.running {
+            if RUNNING.fetch_add(1, Ordering::SeqCst) >= MAX_RUNNING {
+                RUNNING.fetch_sub(1, Ordering::SeqCst);
+                return state.last.clone();
+            }
+            state.running = true;
+            let command = self.command.clone();
+            let timeout = self.timeout;
+            let shared = self.state.clone();
+            std::thread::spawn(move || {
+                let result = run(&command, timeout).and_then(|output| {
+                    parse_output(&output).map_err(|e| format!("{}: {}", command, e))
+                });
+                let mut state = shared.lock().unwrap();
+                state.last = result;
+                state.running = false;
+                RUNNING.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        state.last.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names_values(stats: &[ExecStat]) -> Vec<(&str, u64)> {
+        stats.iter().map(|s| (s.name.as_str(), s.value)).collect()
+    }
+
+    // Poll once to start a run, then wait for it to complete and return its result.
+    fn complete(runner: &Runner) -> Result<Vec<ExecStat>, String> {
+        runner.poll().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while runner.state.lock().unwrap().running {
+            assert!(Instant::now() < deadline, "runner never completed");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        runner.state.lock().unwrap().last.clone()
+    }
+
+    #[test]
+    fn lines() {
+        let stats = parse_output("# header\nrx_bytes 100\ntx_bytes: 200\nbad x\na b c\n").unwrap();
+        assert_eq!(
+            names_values(&stats),
+            vec![("rx_bytes", 100), ("tx_bytes", 200)]
+        );
+    }
+
+    #[test]
+    fn json() {
+        let text = r#" {"ports": [{"rx": 1, "tx": 2}, {"rx": 3, "name": "p\"1"}],
+            "up": true, "load": 0.5, "neg": -1, "empty": {}, "none": []}"#;
+        let stats = parse_output(text).unwrap();
+        assert_eq!(
+            names_values(&stats),
+            vec![("ports.0.rx", 1), ("ports.0.tx", 2), ("ports.1.rx", 3)]
+        );
+        assert!(parse_output("{\"a\": 1").is_err());
+        assert!(parse_output("[1, 2").is_err());
+        assert!(parse_output("{\"a\" 1}").is_err());
+    }
+
+    #[test]
+    fn runner() {
+        let runner = Runner::new("echo 'drops 5'", Duration::from_secs(5));
+        assert_eq!(names_values(&runner.poll().unwrap()), vec![]);
+        assert_eq!(
+            names_values(&complete(&runner).unwrap()),
+            vec![("drops", 5)]
+        );
+    }
+
+    #[test]
+    fn runner_failure() {
+        let runner = Runner::new("echo 'drops 5'; exit 3", Duration::from_secs(5));
+        assert!(complete(&runner).is_err());
+    }
+
+    #[test]
+    fn runner_timeout() {
+        let runner = Runner::new("sleep 5", Duration::from_millis(100));
+        let err = complete(&runner).unwrap_err();
+        assert!(err.contains("timed out"), "{}", err);
+    }
+}
@@ -0,0 +1,88 @@
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct FileStat {
+    // Last component of the matched path.
+    pub name: String,
+    pub captures: Vec<String>,
+    pub value: u64,
+}
+
+fn is_wildcard(component: &str) -> bool {
+    component.contains(|ch| ch == '*' || ch == '?' || ch == '[')
+}
+
+// Split "path:N" into the path and a 1-based field number. Only a trailing all-digit suffix counts
+// as a field, so that paths such as /sys/bus/pci/devices/0000:03:00.0/... are left alone.
+pub fn split_field(spec: &str) -> (&str, Option<usize>) {
+    if let Some(pos) = spec.rfind(':') {
+        if let Ok(field) = spec[pos + 1..].parse::<usize>() {
+            if field > 0 {
+                return (&spec[..pos], Some(field));
+            }
+        }
+    }
+    (spec, None)
+}
+
+// Captures are taken from the components of the matched paths, by their position in the pattern.
+// A recursive wildcard can match any number of components, which would shift them.
+pub fn check_pattern(pattern: &str) -> Result<(), String> {
+    if pattern.contains("**") {
+        return Err(format!(
+            "Recursive wildcards are not supported: {}",
+            pattern
+        ));
+    }
+    Ok(())
+}
+
+// Number of capture columns that rows of `pattern` have.
+pub fn capture_count(pattern: &str) -> usize {
+    pattern.split('/').filter(|comp| is_wildcard(comp)).count()
+}
+
+fn read_value(path: &Path, field: Option<usize>) -> Option<u64> {
+    let text = std::fs::read_to_string(path).ok()?;
+    let word = match field {
+        Some(field) => text.split_whitespace().nth(field - 1)?,
+        None => text.trim(),
+    };
+    word.parse::<u64>().ok()
+}
+
+// Read the files matching the glob `pattern`. Each path component that the pattern has a wildcard
+// in becomes a capture. Files that can't be read or don't contain a number (at the given field)
+// are skipped.
+pub fn stats(pattern: &str, field: Option<usize>) -> Result<Vec<FileStat>, String> {
+    check_pattern(pattern)?;
+    let wildcards: Vec<bool> = pattern.split('/').map(is_wildcard).collect();
+    let mut stats = Vec::new();
+    for path in glob::glob(pattern).map_err(|e| format!("{}: {}", pattern, e.msg))? {
+        let path = match path {
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+        let value = match read_value(&path, field) {
+            Some(value) => value,
+            None => continue,
+        };
+        let path_str = path.to_string_lossy();
+        let captures = path_str
+            .split('/')
+            .zip(wildcards.iter())
+            .filter(|(_, wildcard)| **wildcard)
+            .map(|(comp, _)| comp.to_string())
+            .collect();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        stats.push(FileStat {
+            name: name,
+            captures: captures,
+            value: value,
+        });
+    }
+    Ok(stats)
+}
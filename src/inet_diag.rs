@@ -0,0 +1,153 @@
+use crate::ct;
+use crate::netlink;
+
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+const AF_INET: u8 = 2;
+const AF_INET6: u8 = 10;
+const IPPROTO_TCP: u8 = 6;
+
+const INET_DIAG_INFO: u16 = 2;
+const INET_DIAG_CGROUP_ID: u16 = 21;
+
+// Sizes of struct inet_diag_req_v2 and struct inet_diag_msg.
+const INET_DIAG_REQ_V2_LEN: usize = 56;
+const INET_DIAG_MSG_LEN: usize = 72;
+
+// All states except LISTEN, TIME_WAIT and NEW_SYN_RECV. Those are not full sockets and have no
+// meaningful tcp_info.
+const TCP_STATES: u32 = 0xffe & !(1 << 6) & !(1 << 10);
+
+#[derive(Debug)]
+pub struct SockStat {
+    pub local: SocketAddr,
+    pub remote: SocketAddr,
+    pub cgroup: Option<u64>,
+    pub name: String,
+    pub value: u64,
+    pub default_unit: ct::UnitChain,
+}
+
+// Fields of struct tcp_info: offset, name, size, default unit. The struct has been growing over
+// time, and fields that the running kernel doesn't report are skipped.
+static TCP_INFO: [(usize, &str, usize, fn() -> ct::UnitChain); 42] = [
+    (2, "retransmits", 1, ct::unit_units),
+    (8, "rto", 4, ct::unit_micro_seconds),
+    (12, "ato", 4, ct::unit_micro_seconds),
+    (16, "snd_mss", 4, ct::unit_bytes),
+    (20, "rcv_mss", 4, ct::unit_bytes),
+    (24, "unacked", 4, ct::unit_packets),
+    (28, "sacked", 4, ct::unit_packets),
+    (32, "lost", 4, ct::unit_packets),
+    (36, "retrans", 4, ct::unit_packets),
+    (60, "pmtu", 4, ct::unit_bytes),
+    (64, "rcv_ssthresh", 4, ct::unit_bytes),
+    (68, "rtt", 4, ct::unit_micro_seconds),
+    (72, "rttvar", 4, ct::unit_micro_seconds),
+    (76, "snd_ssthresh", 4, ct::unit_packets),
+    (80, "snd_cwnd", 4, ct::unit_packets),
+    (84, "advmss", 4, ct::unit_bytes),
+    (88, "reordering", 4, ct::unit_packets),
+    (92, "rcv_rtt", 4, ct::unit_micro_seconds),
+    (96, "rcv_space", 4, ct::unit_bytes),
+    (100, "total_retrans", 4, ct::unit_packets_ps),
+    (104, "pacing_rate", 8, ct::unit_bytes_bits_rate),
+    (112, "max_pacing_rate", 8, ct::unit_bytes_bits_rate),
+    (120, "bytes_acked", 8, ct::unit_bytes_bits_ps),
+    (128, "bytes_received", 8, ct::unit_bytes_bits_ps),
+    (136, "segs_out", 4, ct::unit_packets_ps),
+    (140, "segs_in", 4, ct::unit_packets_ps),
+    (144, "notsent_bytes", 4, ct::unit_bytes),
+    (148, "min_rtt", 4, ct::unit_micro_seconds),
+    (152, "data_segs_in", 4, ct::unit_packets_ps),
+    (156, "data_segs_out", 4, ct::unit_packets_ps),
+    (160, "delivery_rate", 8, ct::unit_bytes_bits_rate),
+    (168, "busy_time", 8, ct::unit_micro_seconds),
+    (176, "rwnd_limited", 8, ct::unit_micro_seconds),
+    (184, "sndbuf_limited", 8, ct::unit_micro_seconds),
+    (192, "delivered", 4, ct::unit_packets_ps),
+    (196, "delivered_ce", 4, ct::unit_packets_ps),
+    (200, "bytes_sent", 8, ct::unit_bytes_bits_ps),
+    (208, "bytes_retrans", 8, ct::unit_bytes_bits_ps),
+    (216, "dsack_dups", 4, ct::unit_packets_ps),
+    (220, "reord_seen", 4, ct::unit_packets_ps),
+    (224, "rcv_ooopack", 4, ct::unit_packets_ps),
+    (228, "snd_wnd", 4, ct::unit_bytes),
+];
+
+fn inet_diag_req(family: u8) -> Vec<u8> {
+    let mut req = Vec::with_capacity(INET_DIAG_REQ_V2_LEN);
+    req.push(family);
+    req.push(IPPROTO_TCP);
+    req.push(1 << (INET_DIAG_INFO - 1));
+    req.push(0);
+    req.extend_from_slice(&TCP_STATES.to_ne_bytes());
+    req.resize(INET_DIAG_REQ_V2_LEN, 0);
+    req
+}
+
+// Addresses in struct inet_diag_sockid are stored in 16 bytes regardless of family, in network
+// byte order, as are the ports.
+fn sock_addr(family: u8, port: &[u8], addr: &[u8]) -> SocketAddr {
+    let port = u16::from_be_bytes([port[0], port[1]]);
+    let ip = if family == AF_INET {
+        IpAddr::V4(Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]))
+    } else {
+        let octets: [u8; 16] = addr[..16].try_into().unwrap();
+        IpAddr::V6(Ipv6Addr::from(octets))
+    };
+    SocketAddr::new(ip, port)
+}
+
+fn push_tcp_info(
+    stats: &mut Vec<SockStat>,
+    local: SocketAddr,
+    remote: SocketAddr,
+    cgroup: Option<u64>,
+    info: &[u8],
+) {
+    for (offset, name, size, default_unit) in TCP_INFO.iter() {
+        if offset + size > info.len() {
+            continue;
+        }
+        let field = &info[*offset..offset + size];
+        let value = match size {
+            1 => field[0] as u64,
+            4 => u32::from_ne_bytes(field.try_into().unwrap()) as u64,
+            _ => u64::from_ne_bytes(field.try_into().unwrap()),
+        };
+        stats.push(SockStat {
+            local: local,
+            remote: remote,
+            cgroup: cgroup,
+            name: name.to_string(),
+            value: value,
+            default_unit: default_unit(),
+        });
+    }
+}
+
+// tcp_info fields of all TCP sockets that carry a connection.
+pub fn tcp_stats() -> Result<Vec<SockStat>, String> {
+    let mut stats = Vec::new();
+    for family in &[AF_INET, AF_INET6] {
+        for msg in netlink::sock_diag_dump(inet_diag_req(*family))? {
+            if msg.len() < INET_DIAG_MSG_LEN {
+                return Err("Truncated inet_diag message".to_string());
+            }
+
+            // struct inet_diag_msg starts with family, state, timer and retrans, followed by
+            // struct inet_diag_sockid: sport, dport, src[4], dst[4], if, cookie[2].
+            let local = sock_addr(msg[0], &msg[4..6], &msg[8..24]);
+            let remote = sock_addr(msg[0], &msg[6..8], &msg[24..40]);
+
+            let attrs = netlink::nla_attrs(&msg[INET_DIAG_MSG_LEN..]);
+            let cgroup = netlink::nla_find(&attrs, INET_DIAG_CGROUP_ID).and_then(netlink::nla_u64);
+            if let Some(info) = netlink::nla_find(&attrs, INET_DIAG_INFO) {
+                push_tcp_info(&mut stats, local, remote, cgroup, info);
+            }
+        }
+    }
+    Ok(stats)
+}
@@ -0,0 +1,156 @@
+use std::io::BufRead;
+use std::path::Path;
+
+const PROC_INTERRUPTS: &str = "/proc/interrupts";
+
+#[derive(Debug)]
+pub struct IrqStat {
+    pub irq: String,
+    pub name: String,
+    pub counts: Vec<(u32, u64)>,
+}
+
+// The chip and hwirq columns end with the trigger type, e.g. "IO-APIC-edge" on older kernels, or
+// "IO-APIC 5-edge" on newer ones.
+static TRIGGERS: [&str; 4] = ["edge", "level", "fasteoi", "simple"];
+
+fn is_trigger(word: &str) -> bool {
+    match word.rsplit('-').next() {
+        Some(trigger) => word.contains('-') && TRIGGERS.contains(&trigger),
+        None => false,
+    }
+}
+
+// Action names are printed after the chip and hwirq columns. Several actions sharing one IRQ are
+// separated by ", ", so walk from the end and collect words until one without a trailing comma
+// is found.
+fn action_name(words: &[&str]) -> Option<String> {
+    let mut start = words.len();
+    while start > 0 {
+        start -= 1;
+        if start == 0 || !words[start - 1].ends_with(',') {
+            break;
+        }
+    }
+    if start >= words.len() || is_trigger(words[start]) {
+        return None;
+    }
+    Some(words[start..].join(" "))
+}
+
+// /proc/interrupts has a header line with the online CPUs, and a line per IRQ:
+//
+//                CPU0       CPU1
+//      24:          1          0  IR-PCI-MSI 1048576-edge      eth0-TxRx-0
+//     NMI:          0          0   Non-maskable interrupts
+//     ERR:          0
+//
+// Numbered IRQs are named after the action(s). Those that have no action attached are skipped.
+// The architecture-specific ones (NMI, LOC, ...) use the label as a name.
+pub fn parse_interrupts<R: BufRead>(reader: R) -> Result<Vec<IrqStat>, String> {
+    let mut lines = reader.lines();
+    let header = match lines.next() {
+        Some(header) => header.map_err(|e| e.to_string())?,
+        None => return Ok(Vec::new()),
+    };
+    let cpus = header
+        .split_whitespace()
+        .map(|word| {
+            word.trim_start_matches("CPU")
+                .parse::<u32>()
+                .map_err(|_| format!("Unexpected CPU column: {}", word))
+        })
+        .collect::<Result<Vec<u32>, String>>()?;
+
+    let mut stats = Vec::new();
+    for line in lines {
+        let line = line.map_err(|e| e.to_string())?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let irq = match words.first() {
+            Some(word) if word.ends_with(':') => word.trim_end_matches(':'),
+            Some(word) => return Err(format!("Unexpected IRQ column: {}", word)),
+            None => continue,
+        };
+
+        // Some lines (e.g. ERR:) have a single count instead of one per CPU.
+        let mut counts = Vec::new();
+        for (cpu, word) in cpus.iter().zip(words[1..].iter()) {
+            match word.parse::<u64>() {
+                Ok(count) => counts.push((*cpu, count)),
+                Err(_) => break,
+            }
+        }
+        let rest = &words[1 + counts.len()..];
+
+        let name = if irq.chars().all(|ch| ch.is_digit(10)) {
+            match action_name(rest) {
+                Some(name) => name,
+                None => continue,
+            }
+        } else {
+            irq.to_string()
+        };
+
+        stats.push(IrqStat {
+            irq: irq.to_string(),
+            name: name,
+            counts: counts,
+        });
+    }
+    Ok(stats)
+}
+
+pub fn stats_from(path: &Path) -> Result<Vec<IrqStat>, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_interrupts(std::io::BufReader::new(file))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn stats() -> Result<Vec<IrqStat>, String> {
+    stats_from(Path::new(PROC_INTERRUPTS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interrupts() {
+        let text = "           CPU0       CPU2
+  24:          1          0  IO-APIC   5-edge      ACPI:Ged
+  28:          0          0 PCI-MSIX-0000:00:01.0   0-edge      virtio0-config
+ 130:        100        200  IR-PCI-MSI 1048576-edge      eth0-TxRx-0
+ 131:          5          6  IR-PCI-MSI 1048577-edge      mlx5_comp0@pci:0000:03:00.0, eth1-0
+ 132:          0          0  IR-PCI-MSI 1048578-edge
+   9:          0          0   IO-APIC-fasteoi   acpi
+ NMI:          3          4   Non-maskable interrupts
+ ERR:          7
+";
+        let stats = parse_interrupts(text.as_bytes()).unwrap();
+        let names: Vec<(&str, &str)> = stats
+            .iter()
+            .map(|stat| (stat.irq.as_str(), stat.name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("24", "ACPI:Ged"),
+                ("28", "virtio0-config"),
+                ("130", "eth0-TxRx-0"),
+                ("131", "mlx5_comp0@pci:0000:03:00.0, eth1-0"),
+                ("9", "acpi"),
+                ("NMI", "NMI"),
+                ("ERR", "ERR"),
+            ]
+        );
+        assert_eq!(stats[2].counts, vec![(0, 100), (2, 200)]);
+        assert_eq!(stats[6].counts, vec![(0, 7)]);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(parse_interrupts("CPU0 CPUx\n".as_bytes()).is_err());
+        assert!(parse_interrupts("CPU0\n 24 1 IO-APIC 5-edge foo\n".as_bytes()).is_err());
+        assert_eq!(parse_interrupts("".as_bytes()).unwrap().len(), 0);
+    }
+}
@@ -1,9 +1,21 @@
+mod conntrack;
 mod ct;
+mod devlink;
 mod ethtool_ss;
+mod exec;
+mod file;
+mod inet_diag;
+mod interrupts;
 mod netlink;
+mod nftables;
+mod page_pool;
 mod parse;
+mod rdma;
+mod snmp;
+mod softnet;
+mod xdp;
 
 pub use ct::*;
 pub use ethtool_ss::stats_for;
-pub use netlink::ifnames;
+pub use netlink::{ifnames, new_tick};
 pub use parse::*;
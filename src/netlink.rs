@@ -12,6 +12,8 @@ use neli::socket::*;
 use neli::Nl;
 use neli::StreamReadBuffer;
 use neli::StreamWriteBuffer;
+use std::collections::HashMap;
+use std::convert::TryInto;
 
 struct RtnlLinkStats<T> {
     rx_packets: T,
@@ -132,20 +134,38 @@ where
     }
 }
 
+// Attributes that neli doesn't know about.
+const IFLA_PROP_LIST: u16 = 52;
+const IFLA_ALT_IFNAME: u16 = 53;
+const IFLA_PARENT_DEV_NAME: u16 = 56;
+
+// Nested in IFLA_LINKINFO.
+const IFLA_INFO_KIND: u16 = 1;
+
 struct LinkInfo {
     index: i32,
     ifname: String,
     stats: Option<RtnlLinkStats<u64>>,
+    master: Option<i32>,
+    kind: Option<String>,
+    parentdev: Option<String>,
+    altnames: Vec<String>,
 }
 
 fn ifla_link_info(ifi: Nlmsghdr<u16, Ifinfomsg>) -> Option<LinkInfo> {
     let index = ifi.nl_payload.ifi_index;
     let mut ifname = None;
     let mut stats = None;
+    let mut master = None;
+    let mut kind = None;
+    let mut parentdev = None;
+    let mut altnames = Vec::new();
 
     for attr in ifi.nl_payload.rtattrs {
         let payload: Vec<u8> = attr.rta_payload;
-        match attr.rta_type {
+        // Nests such as IFLA_PROP_LIST come with NLA_F_NESTED set, mask it off like nla_attrs()
+        // does, or they won't match.
+        match Ifla::from(u16::from(attr.rta_type) & 0x3fff) {
             Ifla::Ifname => {
                 // Snip terminating zero.
                 let prefix = &payload[..(payload.len() - 1)];
@@ -186,6 +206,22 @@ fn ifla_link_info(ifi: Nlmsghdr<u16, Ifinfomsg>) -> Option<LinkInfo> {
                     rx_nohandler: stats32.rx_nohandler as u64,
                 });
             }
+            Ifla::Master => {
+                master = nla_u32(&payload).map(|index| index as i32);
+            }
+            Ifla::Linkinfo => {
+                kind = nla_find(&nla_attrs(&payload), IFLA_INFO_KIND).map(nla_string);
+            }
+            Ifla::UnrecognizedVariant(IFLA_PROP_LIST) => {
+                for (nla_type, altname) in nla_attrs(&payload) {
+                    if nla_type == IFLA_ALT_IFNAME {
+                        altnames.push(nla_string(altname));
+                    }
+                }
+            }
+            Ifla::UnrecognizedVariant(IFLA_PARENT_DEV_NAME) => {
+                parentdev = Some(nla_string(&payload));
+            }
             _ => {}
         }
     }
@@ -195,6 +231,10 @@ fn ifla_link_info(ifi: Nlmsghdr<u16, Ifinfomsg>) -> Option<LinkInfo> {
             index: index,
             ifname: ifname.unwrap(),
             stats: stats,
+            master: master,
+            kind: kind,
+            parentdev: parentdev,
+            altnames: altnames,
         })
     } else {
         None
@@ -243,6 +283,380 @@ pub fn ifindex_map() -> std::collections::HashMap<libc::c_int, String> {
         .collect()
 }
 
+#[derive(Debug)]
+pub struct LinkAttrs {
+    pub ifname: String,
+    pub driver: Option<String>,
+    pub master: Option<String>,
+    pub kind: Option<String>,
+    pub parentdev: Option<String>,
+    pub altnames: Vec<String>,
+}
+
+thread_local! {
+    static LINK_ATTRS: std::cell::RefCell<Option<std::rc::Rc<HashMap<String, LinkAttrs>>>> =
+        std::cell::RefCell::new(None);
+}
+
+// Forget what was cached during the previous tick. Call this before collecting counters.
+pub fn new_tick() {
+    LINK_ATTRS.with(|cache| *cache.borrow_mut() = None);
+}
+
+// Attributes that interfaces can be selected by, keyed by interface name. They are collected at
+// most once per tick, however many rules select interfaces by them.
+pub fn link_attrs() -> std::rc::Rc<HashMap<String, LinkAttrs>> {
+    LINK_ATTRS.with(|cache| {
+        cache
+            .borrow_mut()
+            .get_or_insert_with(|| {
+                std::rc::Rc::new(
+                    get_link_attrs()
+                        .into_iter()
+                        .map(|link| (link.ifname.clone(), link))
+                        .collect(),
+                )
+            })
+            .clone()
+    })
+}
+
+// The driver is not reported over netlink, and is taken from the device link in sysfs instead.
+fn get_link_attrs() -> Vec<LinkAttrs> {
+    let linkinfo = get_linkinfo();
+    let names: HashMap<i32, String> = linkinfo
+        .iter()
+        .map(|li| (li.index, li.ifname.clone()))
+        .collect();
+    linkinfo
+        .into_iter()
+        .map(|li| LinkAttrs {
+            driver: std::fs::read_link(format!("/sys/class/net/{}/device/driver", li.ifname))
+                .ok()
+                .and_then(|path| {
+                    path.file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                }),
+            master: li.master.and_then(|index| names.get(&index).cloned()),
+            ifname: li.ifname,
+            kind: li.kind,
+            parentdev: li.parentdev,
+            altnames: li.altnames,
+        })
+        .collect()
+}
+
+// Walk a buffer of netlink attributes, such as the payload of a nested attribute. Yields
+// (type, payload) pairs, with the NLA_F_NESTED and NLA_F_NET_BYTEORDER flags masked off the type.
+pub fn nla_attrs(mut buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attrs = Vec::new();
+    while buf.len() >= 4 {
+        let len = u16::from_ne_bytes([buf[0], buf[1]]) as usize;
+        let nla_type = u16::from_ne_bytes([buf[2], buf[3]]) & 0x3fff;
+        if len < 4 || len > buf.len() {
+            break;
+        }
+        attrs.push((nla_type, &buf[4..len]));
+        let aligned = (len + 3) & !3;
+        buf = &buf[std::cmp::min(aligned, buf.len())..];
+    }
+    attrs
+}
+
+pub fn nla_find<'a>(attrs: &[(u16, &'a [u8])], nla_type: u16) -> Option<&'a [u8]> {
+    attrs
+        .iter()
+        .find(|(a_type, _)| *a_type == nla_type)
+        .map(|(_, payload)| *payload)
+}
+
+pub fn nla_put(buf: &mut Vec<u8>, nla_type: u16, payload: &[u8]) {
+    buf.extend_from_slice(&((4 + payload.len()) as u16).to_ne_bytes());
+    buf.extend_from_slice(&nla_type.to_ne_bytes());
+    buf.extend_from_slice(payload);
+    while buf.len() % 4 != 0 {
+        buf.push(0);
+    }
+}
+
+pub fn nla_u32(payload: &[u8]) -> Option<u32> {
+    payload.try_into().ok().map(u32::from_ne_bytes)
+}
+
+pub fn nla_u64(payload: &[u8]) -> Option<u64> {
+    payload.try_into().ok().map(u64::from_ne_bytes)
+}
+
+// NLA_UINT attributes are sized to fit the value, either 32 or 64 bits.
+pub fn nla_uint(payload: &[u8]) -> Option<u64> {
+    nla_u64(payload).or_else(|| nla_u32(payload).map(|value| value as u64))
+}
+
+// Netfilter attributes are in network byte order.
+pub fn nla_be32(payload: &[u8]) -> Option<u32> {
+    payload.try_into().ok().map(u32::from_be_bytes)
+}
+
+pub fn nla_be64(payload: &[u8]) -> Option<u64> {
+    payload.try_into().ok().map(u64::from_be_bytes)
+}
+
+pub fn nla_string(payload: &[u8]) -> String {
+    // Snip terminating zero.
+    let len = payload
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(payload.len());
+    String::from_utf8_lossy(&payload[..len]).into_owned()
+}
+
+// A generic netlink message, with the attributes kept as a raw buffer to be walked by nla_attrs().
+pub struct GenlRaw {
+    pub cmd: u8,
+    pub version: u8,
+    pub attrs: Vec<u8>,
+}
+
+impl Nl for GenlRaw {
+    fn serialize(&self, mem: &mut StreamWriteBuffer) -> Result<(), SerError> {
+        self.cmd.serialize(mem)?;
+        self.version.serialize(mem)?;
+        0u16.serialize(mem)?;
+        self.attrs.serialize(mem)?;
+        Ok(())
+    }
+
+    fn deserialize<B>(mem: &mut StreamReadBuffer<B>) -> Result<Self, DeError>
+    where
+        B: AsRef<[u8]>,
+    {
+        let size_hint = mem.take_size_hint();
+        let cmd = u8::deserialize(mem)?;
+        let version = u8::deserialize(mem)?;
+        u16::deserialize(mem)?;
+        if let Some(size_hint) = size_hint {
+            mem.set_size_hint(size_hint.saturating_sub(4));
+        }
+        Ok(GenlRaw {
+            cmd: cmd,
+            version: version,
+            attrs: Vec::<u8>::deserialize(mem)?,
+        })
+    }
+
+    fn size(&self) -> usize {
+        self.cmd.size() + self.version.size() + 0u16.size() + self.attrs.size()
+    }
+}
+
+// Send a request on `socket` and collect the responses up to NLMSG_DONE, or up to the ACK for
+// requests that ask for one. An error response fails the request, except for EOPNOTSUPP and
+// ENOENT, so that e.g. a devlink instance that doesn't support a command simply yields nothing.
+fn nl_request<P: Nl>(
+    socket: &mut NlSocket,
+    nl_type: u16,
+    flags: Vec<NlmF>,
+    payload: P,
+    what: &str,
+) -> Result<Vec<P>, String> {
+    let nlhdr = {
+        let len = None;
+        let seq = None;
+        let pid = None;
+        Nlmsghdr::new(len, nl_type, flags, seq, pid, payload)
+    };
+
+    socket
+        .send_nl(nlhdr)
+        .map_err(|err| format!("{}: {}", what, err))?;
+
+    // Unlike the fixed-size rtnetlink headers, the raw payloads would happily decode NLMSG_DONE
+    // and NLMSG_ERROR, so receive the bytes and check the message type before decoding them.
+    let mut ret = Vec::new();
+    loop {
+        let nl = socket
+            .recv_nl::<u16, NlRaw>(None)
+            .map_err(|err| format!("{}: {}", what, err))?;
+        let payload = nl.nl_payload.0;
+        if nl.nl_type == libc::NLMSG_DONE as u16 || nl.nl_type == libc::NLMSG_ERROR as u16 {
+            // Both start with a negated errno. It is 0 for an ACK, or when a dump went through.
+            let errno = match payload.get(..4) {
+                Some(b) => -i32::from_ne_bytes([b[0], b[1], b[2], b[3]]),
+                None => 0,
+            };
+            match errno {
+                0 | libc::EOPNOTSUPP | libc::ENOENT => break,
+                _ => {
+                    return Err(format!(
+                        "{}: {}",
+                        what,
+                        std::io::Error::from_raw_os_error(errno)
+                    ))
+                }
+            }
+        }
+        let mut buf = StreamReadBuffer::new(&payload);
+        buf.set_size_hint(payload.len());
+        ret.push(P::deserialize(&mut buf).map_err(|err| format!("{}: {}", what, err))?);
+    }
+    Ok(ret)
+}
+
+fn genl_request(
+    family: &str,
+    version: u8,
+    cmd: u8,
+    flags: Vec<NlmF>,
+    attrs: Vec<u8>,
+) -> Result<Vec<GenlRaw>, String> {
+    let mut socket = NlSocket::connect(NlFamily::Generic, None, None, true)
+        .map_err(|err| format!("{}: {}", family, err))?;
+    let family_id = socket
+        .resolve_genl_family(family)
+        .map_err(|err| format!("{}: {}", family, err))?;
+    let payload = GenlRaw {
+        cmd: cmd,
+        version: version,
+        attrs: attrs,
+    };
+    nl_request(&mut socket, family_id, flags, payload, family)
+}
+
+pub fn genl_dump(
+    family: &str,
+    version: u8,
+    cmd: u8,
+    attrs: Vec<u8>,
+) -> Result<Vec<GenlRaw>, String> {
+    genl_request(family, version, cmd, vec![NlmF::Request, NlmF::Dump], attrs)
+}
+
+// For commands that are not dumps, but are nonetheless answered with a multipart message, such as
+// DEVLINK_CMD_RESOURCE_DUMP.
+pub fn genl_multi(
+    family: &str,
+    version: u8,
+    cmd: u8,
+    attrs: Vec<u8>,
+) -> Result<Vec<GenlRaw>, String> {
+    genl_request(family, version, cmd, vec![NlmF::Request], attrs)
+}
+
+// An nfnetlink message. Like with GenlRaw, the attributes are kept as a raw buffer.
+pub struct NfgenRaw {
+    pub family: u8,
+    pub version: u8,
+    pub res_id: u16,
+    pub attrs: Vec<u8>,
+}
+
+impl Nl for NfgenRaw {
+    fn serialize(&self, mem: &mut StreamWriteBuffer) -> Result<(), SerError> {
+        self.family.serialize(mem)?;
+        self.version.serialize(mem)?;
+        self.res_id.to_be().serialize(mem)?;
+        self.attrs.serialize(mem)?;
+        Ok(())
+    }
+
+    fn deserialize<B>(mem: &mut StreamReadBuffer<B>) -> Result<Self, DeError>
+    where
+        B: AsRef<[u8]>,
+    {
+        let size_hint = mem.take_size_hint();
+        let family = u8::deserialize(mem)?;
+        let version = u8::deserialize(mem)?;
+        let res_id = u16::from_be(u16::deserialize(mem)?);
+        if let Some(size_hint) = size_hint {
+            mem.set_size_hint(size_hint.saturating_sub(4));
+        }
+        Ok(NfgenRaw {
+            family: family,
+            version: version,
+            res_id: res_id,
+            attrs: Vec::<u8>::deserialize(mem)?,
+        })
+    }
+
+    fn size(&self) -> usize {
+        self.family.size() + self.version.size() + self.res_id.size() + self.attrs.size()
+    }
+}
+
+const NFNETLINK_V0: u8 = 0;
+
+fn nfnl_request(
+    subsys: u8,
+    msg: u8,
+    family: u8,
+    flags: Vec<NlmF>,
+) -> Result<Vec<NfgenRaw>, String> {
+    let mut socket = NlSocket::connect(NlFamily::Netfilter, None, None, true)
+        .map_err(|err| format!("nfnetlink: {}", err))?;
+    let payload = NfgenRaw {
+        family: family,
+        version: NFNETLINK_V0,
+        res_id: 0,
+        attrs: Vec::new(),
+    };
+    nl_request(
+        &mut socket,
+        (subsys as u16) << 8 | msg as u16,
+        flags,
+        payload,
+        "nfnetlink",
+    )
+}
+
+// Dump the nfnetlink subsystem `subsys` with message `msg`. Family is an NFPROTO_* value, with
+// NFPROTO_UNSPEC dumping all families.
+pub fn nfnl_dump(subsys: u8, msg: u8, family: u8) -> Result<Vec<NfgenRaw>, String> {
+    nfnl_request(subsys, msg, family, vec![NlmF::Request, NlmF::Dump])
+}
+
+// Like nfnl_dump(), but for messages that are answered by a single reply. The ACK that follows it
+// terminates the list.
+pub fn nfnl_get(subsys: u8, msg: u8, family: u8) -> Result<Vec<NfgenRaw>, String> {
+    nfnl_request(subsys, msg, family, vec![NlmF::Request, NlmF::Ack])
+}
+
+// A payload kept as raw bytes, for messages whose fixed header is simpler to decode by hand.
+pub struct NlRaw(pub Vec<u8>);
+
+impl Nl for NlRaw {
+    fn serialize(&self, mem: &mut StreamWriteBuffer) -> Result<(), SerError> {
+        self.0.serialize(mem)
+    }
+
+    fn deserialize<B>(mem: &mut StreamReadBuffer<B>) -> Result<Self, DeError>
+    where
+        B: AsRef<[u8]>,
+    {
+        Ok(NlRaw(Vec::<u8>::deserialize(mem)?))
+    }
+
+    fn size(&self) -> usize {
+        self.0.size()
+    }
+}
+
+const SOCK_DIAG_BY_FAMILY: u16 = 20;
+
+// Dump sockets matching the request `req`, e.g. an inet_diag_req_v2. Each response is returned as
+// raw bytes, i.e. the family-specific header followed by attributes.
+pub fn sock_diag_dump(req: Vec<u8>) -> Result<Vec<Vec<u8>>, String> {
+    let mut socket = NlSocket::connect(NlFamily::from(libc::NETLINK_SOCK_DIAG), None, None, true)
+        .map_err(|err| format!("sock_diag: {}", err))?;
+    let ret = nl_request(
+        &mut socket,
+        SOCK_DIAG_BY_FAMILY,
+        vec![NlmF::Request, NlmF::Dump],
+        NlRaw(req),
+        "sock_diag",
+    )?;
+    Ok(ret.into_iter().map(|raw| raw.0).collect())
+}
+
 neli::impl_var_trait!(
     /// Enum for use with `Rtattr.rta_type`.
     /// Values are nested attributes to TCA_STATS2
@@ -365,6 +779,61 @@ where
     }
 }
 
+// struct tc_stats, the legacy TCA_STATS payload. It combines what TCA_STATS2 reports in
+// TCA_STATS_BASIC, TCA_STATS_RATE_EST and TCA_STATS_QUEUE.
+#[derive(Debug)]
+struct TcStats {
+    bytes: u64,
+    packets: u32,
+    drops: u32,
+    overlimits: u32,
+    bps: u32,
+    pps: u32,
+    qlen: u32,
+    backlog: u32,
+}
+
+impl Nl for TcStats {
+    fn serialize(&self, mem: &mut StreamWriteBuffer) -> Result<(), SerError> {
+        self.bytes.serialize(mem)?;
+        self.packets.serialize(mem)?;
+        self.drops.serialize(mem)?;
+        self.overlimits.serialize(mem)?;
+        self.bps.serialize(mem)?;
+        self.pps.serialize(mem)?;
+        self.qlen.serialize(mem)?;
+        self.backlog.serialize(mem)?;
+        Ok(())
+    }
+
+    fn deserialize<B>(mem: &mut StreamReadBuffer<B>) -> Result<Self, DeError>
+    where
+        B: AsRef<[u8]>,
+    {
+        Ok(TcStats {
+            bytes: u64::deserialize(mem)?,
+            packets: u32::deserialize(mem)?,
+            drops: u32::deserialize(mem)?,
+            overlimits: u32::deserialize(mem)?,
+            bps: u32::deserialize(mem)?,
+            pps: u32::deserialize(mem)?,
+            qlen: u32::deserialize(mem)?,
+            backlog: u32::deserialize(mem)?,
+        })
+    }
+
+    fn size(&self) -> usize {
+        self.bytes.size()
+            + self.packets.size()
+            + self.drops.size()
+            + self.overlimits.size()
+            + self.bps.size()
+            + self.pps.size()
+            + self.qlen.size()
+            + self.backlog.size()
+    }
+}
+
 #[derive(Debug)]
 pub struct LinkStat {
     pub ifname: String,
@@ -422,6 +891,7 @@ pub struct QdiscStat {
     pub kind: String,
     pub handle: u32,
     pub parent: u32,
+    pub vq: Option<u32>,
     pub name: String,
     pub value: u64,
     pub default_unit: ct::UnitChain,
@@ -432,6 +902,8 @@ struct QdiscStatsAux {
     ifname: String,
     handle: u32,
     parent: u32,
+    // Virtual queue that pushed counters belong to, for qdiscs like GRED.
+    vq: Option<u32>,
 }
 
 impl QdiscStatsAux {
@@ -441,6 +913,7 @@ impl QdiscStatsAux {
             ifname: ifname.to_string(),
             handle: handle,
             parent: parent,
+            vq: None,
         }
     }
     fn push_counter(&mut self, kind: &String, name: &str, value: u64, default_unit: ct::UnitChain) {
@@ -449,15 +922,47 @@ impl QdiscStatsAux {
             kind: (*kind).clone(),
             handle: self.handle,
             parent: self.parent,
+            vq: self.vq,
             name: name.to_string(),
             value: value,
             default_unit: default_unit,
         });
     }
+    // Run a parser for an optional part of the qdisc attributes, like the options or xstats. If
+    // it fails, drop whatever counters it pushed, but keep the ones from the other parts.
+    fn optional<E, F>(&mut self, parse: F)
+    where
+        F: FnOnce(&mut QdiscStatsAux) -> Result<(), E>,
+    {
+        let len = self.stats.len();
+        let vq = self.vq;
+        if parse(self).is_err() {
+            self.stats.truncate(len);
+            self.vq = vq;
+        }
+    }
 }
 
 trait QdiscAppParser {
-    fn parse_app(&self, kind: String, aux: &mut QdiscStatsAux, payload: &Vec<u8>);
+    // Decode TCA_STATS_APP.
+    fn parse_app(
+        &self,
+        _kind: String,
+        _aux: &mut QdiscStatsAux,
+        _payload: &Vec<u8>,
+    ) -> Result<(), DeError> {
+        Ok(())
+    }
+
+    // Some qdiscs report statistics as part of TCA_OPTIONS instead.
+    fn parse_options(
+        &self,
+        _kind: String,
+        _aux: &mut QdiscStatsAux,
+        _payload: &Vec<u8>,
+    ) -> Result<(), DeError> {
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -497,23 +1002,749 @@ impl Nl for TcRedXstats {
 struct QdiscAppParserRed {}
 
 impl QdiscAppParser for QdiscAppParserRed {
-    fn parse_app(&self, kind: String, aux: &mut QdiscStatsAux, payload: &Vec<u8>) {
+    fn parse_app(
+        &self,
+        kind: String,
+        aux: &mut QdiscStatsAux,
+        payload: &Vec<u8>,
+    ) -> Result<(), DeError> {
         let mut buf = StreamReadBuffer::new(&payload);
-        let xstats = TcRedXstats::deserialize(&mut buf).unwrap();
+        let xstats = TcRedXstats::deserialize(&mut buf)?;
         aux.push_counter(&kind, "early", xstats.early as u64, ct::unit_packets_ps());
         aux.push_counter(&kind, "pdrop", xstats.pdrop as u64, ct::unit_packets_ps());
         aux.push_counter(&kind, "other", xstats.other as u64, ct::unit_packets_ps());
         aux.push_counter(&kind, "marked", xstats.marked as u64, ct::unit_packets_ps());
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+enum XstatsType {
+    U32,
+    S32,
+    U64,
+    S64,
+}
+
+impl XstatsType {
+    fn size(self) -> usize {
+        match self {
+            XstatsType::U32 | XstatsType::S32 => 4,
+            XstatsType::U64 | XstatsType::S64 => 8,
+        }
+    }
+
+    // Signed fields are gauges such as token counts or deficits. Negative values are shown as 0.
+    fn decode(self, bytes: &[u8]) -> Option<u64> {
+        if bytes.len() != self.size() {
+            return None;
+        }
+        Some(match self {
+            XstatsType::U32 => u32::from_ne_bytes(bytes.try_into().unwrap()) as u64,
+            XstatsType::S32 => {
+                std::cmp::max(0, i32::from_ne_bytes(bytes.try_into().unwrap())) as u64
+            }
+            XstatsType::U64 => u64::from_ne_bytes(bytes.try_into().unwrap()),
+            XstatsType::S64 => {
+                std::cmp::max(0, i64::from_ne_bytes(bytes.try_into().unwrap())) as u64
+            }
+        })
+    }
+}
+
+type XstatsField = (&'static str, XstatsType, fn() -> ct::UnitChain);
+
+// Like XstatsField, but for statistics sent as netlink attributes. The first element is the
+// attribute type.
+type XstatsAttr = (u16, &'static str, XstatsType, fn() -> ct::UnitChain);
+
+// Decode a flat xstats structure described by `fields`. Several of the structures grew over time,
+// so fields past the end of the payload are assumed to not be supported by the kernel and skipped.
+// A payload too short for even the first field is an error.
+fn push_xstats(
+    kind: &String,
+    aux: &mut QdiscStatsAux,
+    payload: &[u8],
+    fields: &[XstatsField],
+) -> Result<(), DeError> {
+    if let Some((_, ty, _)) = fields.first() {
+        if payload.len() < ty.size() {
+            return Err(DeError::new("Xstats payload too short"));
+        }
+    }
+
+    let mut offset = 0;
+    for (name, ty, default_unit) in fields {
+        let end = offset + ty.size();
+        if end > payload.len() {
+            break;
+        }
+        if let Some(value) = ty.decode(&payload[offset..end]) {
+            aux.push_counter(kind, name, value, default_unit());
+        }
+        offset = end;
+    }
+    Ok(())
+}
+
+// struct tc_fq_codel_xstats is a u32 type followed by a union of the following two.
+const TCA_FQ_CODEL_XSTATS_QDISC: u32 = 0;
+const TCA_FQ_CODEL_XSTATS_CLASS: u32 = 1;
+
+// struct tc_fq_codel_qd_stats
+static FQ_CODEL_QD_XSTATS: [XstatsField; 9] = [
+    ("maxpacket", XstatsType::U32, ct::unit_bytes),
+    ("drop_overlimit", XstatsType::U32, ct::unit_packets_ps),
+    ("ecn_mark", XstatsType::U32, ct::unit_packets_ps),
+    ("new_flow_count", XstatsType::U32, ct::unit_units_ps),
+    ("new_flows_len", XstatsType::U32, ct::unit_units),
+    ("old_flows_len", XstatsType::U32, ct::unit_units),
+    ("ce_mark", XstatsType::U32, ct::unit_packets_ps),
+    ("memory_usage", XstatsType::U32, ct::unit_bytes),
+    ("drop_overmemory", XstatsType::U32, ct::unit_packets_ps),
+];
+
+// struct tc_fq_codel_cl_stats
+static FQ_CODEL_CL_XSTATS: [XstatsField; 6] = [
+    ("deficit", XstatsType::S32, ct::unit_bytes),
+    ("ldelay", XstatsType::U32, ct::unit_micro_seconds),
+    ("count", XstatsType::U32, ct::unit_units),
+    ("lastcount", XstatsType::U32, ct::unit_units),
+    ("dropping", XstatsType::U32, ct::unit_units),
+    ("drop_next", XstatsType::S32, ct::unit_micro_seconds),
+];
+
+struct QdiscAppParserFqCodel {}
+
+impl QdiscAppParser for QdiscAppParserFqCodel {
+    fn parse_app(
+        &self,
+        kind: String,
+        aux: &mut QdiscStatsAux,
+        payload: &Vec<u8>,
+    ) -> Result<(), DeError> {
+        if payload.len() < 4 {
+            return Err(DeError::new("fq_codel xstats payload too short"));
+        }
+        let (xtype, rest) = payload.split_at(4);
+        match u32::from_ne_bytes(xtype.try_into().unwrap()) {
+            TCA_FQ_CODEL_XSTATS_QDISC => push_xstats(&kind, aux, rest, &FQ_CODEL_QD_XSTATS),
+            TCA_FQ_CODEL_XSTATS_CLASS => push_xstats(&kind, aux, rest, &FQ_CODEL_CL_XSTATS),
+            _ => Ok(()),
+        }
+    }
+}
+
+// struct tc_codel_xstats
+static CODEL_XSTATS: [XstatsField; 9] = [
+    ("maxpacket", XstatsType::U32, ct::unit_bytes),
+    ("count", XstatsType::U32, ct::unit_units),
+    ("lastcount", XstatsType::U32, ct::unit_units),
+    ("ldelay", XstatsType::U32, ct::unit_micro_seconds),
+    ("drop_next", XstatsType::S32, ct::unit_micro_seconds),
+    ("drop_overlimit", XstatsType::U32, ct::unit_packets_ps),
+    ("ecn_mark", XstatsType::U32, ct::unit_packets_ps),
+    ("dropping", XstatsType::U32, ct::unit_units),
+    ("ce_mark", XstatsType::U32, ct::unit_packets_ps),
+];
+
+struct QdiscAppParserCodel {}
+
+impl QdiscAppParser for QdiscAppParserCodel {
+    fn parse_app(
+        &self,
+        kind: String,
+        aux: &mut QdiscStatsAux,
+        payload: &Vec<u8>,
+    ) -> Result<(), DeError> {
+        push_xstats(&kind, aux, payload, &CODEL_XSTATS)
+    }
+}
+
+// struct tc_pie_xstats. Since 5.7, prob is a u64 and dq_rate_estimating was added.
+static PIE_XSTATS: [XstatsField; 9] = [
+    ("prob", XstatsType::U64, ct::unit_units),
+    ("delay", XstatsType::U32, ct::unit_micro_seconds),
+    ("avg_dq_rate", XstatsType::U32, ct::unit_units),
+    ("dq_rate_estimating", XstatsType::U32, ct::unit_units),
+    ("packets_in", XstatsType::U32, ct::unit_packets_ps),
+    ("dropped", XstatsType::U32, ct::unit_packets_ps),
+    ("overlimit", XstatsType::U32, ct::unit_packets_ps),
+    ("maxq", XstatsType::U32, ct::unit_packets),
+    ("ecn_mark", XstatsType::U32, ct::unit_packets_ps),
+];
+
+static PIE_XSTATS_OLD: [XstatsField; 8] = [
+    ("prob", XstatsType::U32, ct::unit_units),
+    ("delay", XstatsType::U32, ct::unit_micro_seconds),
+    ("avg_dq_rate", XstatsType::U32, ct::unit_units),
+    ("packets_in", XstatsType::U32, ct::unit_packets_ps),
+    ("dropped", XstatsType::U32, ct::unit_packets_ps),
+    ("overlimit", XstatsType::U32, ct::unit_packets_ps),
+    ("maxq", XstatsType::U32, ct::unit_packets),
+    ("ecn_mark", XstatsType::U32, ct::unit_packets_ps),
+];
+
+struct QdiscAppParserPie {}
+
+impl QdiscAppParser for QdiscAppParserPie {
+    fn parse_app(
+        &self,
+        kind: String,
+        aux: &mut QdiscStatsAux,
+        payload: &Vec<u8>,
+    ) -> Result<(), DeError> {
+        if payload.len() < 40 {
+            push_xstats(&kind, aux, payload, &PIE_XSTATS_OLD)
+        } else {
+            push_xstats(&kind, aux, payload, &PIE_XSTATS)
+        }
+    }
+}
+
+// struct tc_fq_pie_xstats
+static FQ_PIE_XSTATS: [XstatsField; 9] = [
+    ("packets_in", XstatsType::U32, ct::unit_packets_ps),
+    ("dropped", XstatsType::U32, ct::unit_packets_ps),
+    ("overlimit", XstatsType::U32, ct::unit_packets_ps),
+    ("overmemory", XstatsType::U32, ct::unit_packets_ps),
+    ("ecn_mark", XstatsType::U32, ct::unit_packets_ps),
+    ("new_flow_count", XstatsType::U32, ct::unit_units_ps),
+    ("new_flows_len", XstatsType::U32, ct::unit_units),
+    ("old_flows_len", XstatsType::U32, ct::unit_units),
+    ("memory_usage", XstatsType::U32, ct::unit_bytes),
+];
+
+struct QdiscAppParserFqPie {}
+
+impl QdiscAppParser for QdiscAppParserFqPie {
+    fn parse_app(
+        &self,
+        kind: String,
+        aux: &mut QdiscStatsAux,
+        payload: &Vec<u8>,
+    ) -> Result<(), DeError> {
+        push_xstats(&kind, aux, payload, &FQ_PIE_XSTATS)
+    }
+}
+
+// struct tc_fq_qd_stats. The fields from ce_mark on were added gradually in 5.x and 6.x kernels.
+static FQ_XSTATS: [XstatsField; 22] = [
+    ("gc_flows", XstatsType::U64, ct::unit_units_ps),
+    ("highprio_packets", XstatsType::U64, ct::unit_packets_ps),
+    ("tcp_retrans", XstatsType::U64, ct::unit_packets_ps),
+    ("throttled", XstatsType::U64, ct::unit_units_ps),
+    ("flows_plimit", XstatsType::U64, ct::unit_packets_ps),
+    ("pkts_too_long", XstatsType::U64, ct::unit_packets_ps),
+    ("allocation_errors", XstatsType::U64, ct::unit_units_ps),
+    (
+        "time_next_delayed_flow",
+        XstatsType::S64,
+        ct::unit_nano_seconds,
+    ),
+    ("flows", XstatsType::U32, ct::unit_units),
+    ("inactive_flows", XstatsType::U32, ct::unit_units),
+    ("throttled_flows", XstatsType::U32, ct::unit_units),
+    (
+        "unthrottle_latency_ns",
+        XstatsType::U32,
+        ct::unit_nano_seconds,
+    ),
+    ("ce_mark", XstatsType::U64, ct::unit_packets_ps),
+    ("horizon_drops", XstatsType::U64, ct::unit_packets_ps),
+    ("horizon_caps", XstatsType::U64, ct::unit_packets_ps),
+    ("fastpath_packets", XstatsType::U64, ct::unit_packets_ps),
+    ("band0_drops", XstatsType::U64, ct::unit_packets_ps),
+    ("band1_drops", XstatsType::U64, ct::unit_packets_ps),
+    ("band2_drops", XstatsType::U64, ct::unit_packets_ps),
+    ("band0_pkt_count", XstatsType::U32, ct::unit_packets),
+    ("band1_pkt_count", XstatsType::U32, ct::unit_packets),
+    ("band2_pkt_count", XstatsType::U32, ct::unit_packets),
+];
+
+struct QdiscAppParserFq {}
+
+impl QdiscAppParser for QdiscAppParserFq {
+    fn parse_app(
+        &self,
+        kind: String,
+        aux: &mut QdiscStatsAux,
+        payload: &Vec<u8>,
+    ) -> Result<(), DeError> {
+        push_xstats(&kind, aux, payload, &FQ_XSTATS)
+    }
+}
+
+// struct tc_sfq_xstats
+static SFQ_XSTATS: [XstatsField; 1] = [("allot", XstatsType::S32, ct::unit_bytes)];
+
+struct QdiscAppParserSfq {}
+
+impl QdiscAppParser for QdiscAppParserSfq {
+    fn parse_app(
+        &self,
+        kind: String,
+        aux: &mut QdiscStatsAux,
+        payload: &Vec<u8>,
+    ) -> Result<(), DeError> {
+        push_xstats(&kind, aux, payload, &SFQ_XSTATS)
     }
 }
 
-const QDISC_APP_PARSERS: [(&str, &dyn QdiscAppParser); 1] = [("red", &QdiscAppParserRed {})];
+// struct tc_choke_xstats
+static CHOKE_XSTATS: [XstatsField; 5] = [
+    ("early", XstatsType::U32, ct::unit_packets_ps),
+    ("pdrop", XstatsType::U32, ct::unit_packets_ps),
+    ("other", XstatsType::U32, ct::unit_packets_ps),
+    ("marked", XstatsType::U32, ct::unit_packets_ps),
+    ("matched", XstatsType::U32, ct::unit_packets_ps),
+];
 
-pub fn qdiscs() -> Vec<QdiscStat> {
+struct QdiscAppParserChoke {}
+
+impl QdiscAppParser for QdiscAppParserChoke {
+    fn parse_app(
+        &self,
+        kind: String,
+        aux: &mut QdiscStatsAux,
+        payload: &Vec<u8>,
+    ) -> Result<(), DeError> {
+        push_xstats(&kind, aux, payload, &CHOKE_XSTATS)
+    }
+}
+
+// struct tc_htb_xstats. Tokens are in scheduler ticks.
+static HTB_XSTATS: [XstatsField; 5] = [
+    ("lends", XstatsType::U32, ct::unit_packets_ps),
+    ("borrows", XstatsType::U32, ct::unit_packets_ps),
+    ("giants", XstatsType::U32, ct::unit_packets_ps),
+    ("tokens", XstatsType::S32, ct::unit_units),
+    ("ctokens", XstatsType::S32, ct::unit_units),
+];
+
+struct QdiscAppParserHtb {}
+
+impl QdiscAppParser for QdiscAppParserHtb {
+    fn parse_app(
+        &self,
+        kind: String,
+        aux: &mut QdiscStatsAux,
+        payload: &Vec<u8>,
+    ) -> Result<(), DeError> {
+        push_xstats(&kind, aux, payload, &HTB_XSTATS)
+    }
+}
+
+// struct tc_hfsc_stats
+static HFSC_XSTATS: [XstatsField; 4] = [
+    ("work", XstatsType::U64, ct::unit_bytes_bits_ps),
+    ("rtwork", XstatsType::U64, ct::unit_bytes_bits_ps),
+    ("period", XstatsType::U32, ct::unit_units),
+    ("level", XstatsType::U32, ct::unit_units),
+];
+
+struct QdiscAppParserHfsc {}
+
+impl QdiscAppParser for QdiscAppParserHfsc {
+    fn parse_app(
+        &self,
+        kind: String,
+        aux: &mut QdiscStatsAux,
+        payload: &Vec<u8>,
+    ) -> Result<(), DeError> {
+        push_xstats(&kind, aux, payload, &HFSC_XSTATS)
+    }
+}
+
+// Cake sends its xstats as nested attributes. TCA_CAKE_STATS_*:
+const TCA_CAKE_STATS_TIN_STATS: u16 = 10;
+static CAKE_XSTATS: [XstatsAttr; 14] = [
+    (
+        2,
+        "capacity_estimate",
+        XstatsType::U64,
+        ct::unit_bytes_bits_rate,
+    ),
+    (3, "memory_limit", XstatsType::U32, ct::unit_bytes),
+    (4, "memory_used", XstatsType::U32, ct::unit_bytes),
+    (5, "avg_netoff", XstatsType::U32, ct::unit_bytes),
+    (6, "min_netlen", XstatsType::U32, ct::unit_bytes),
+    (7, "max_netlen", XstatsType::U32, ct::unit_bytes),
+    (8, "min_adjlen", XstatsType::U32, ct::unit_bytes),
+    (9, "max_adjlen", XstatsType::U32, ct::unit_bytes),
+    (11, "deficit", XstatsType::S32, ct::unit_bytes),
+    (12, "cobalt_count", XstatsType::U32, ct::unit_units),
+    (13, "dropping", XstatsType::U32, ct::unit_units),
+    (14, "drop_next", XstatsType::S32, ct::unit_micro_seconds),
+    (15, "p_drop", XstatsType::U32, ct::unit_units),
+    (16, "blue_timer", XstatsType::S32, ct::unit_micro_seconds),
+];
+
+// TCA_CAKE_STATS_TIN_STATS holds one nested attribute per tin, whose type is the tin number
+// starting at 1. Each of those holds TCA_CAKE_TIN_STATS_*:
+static CAKE_TIN_XSTATS: [XstatsAttr; 24] = [
+    (2, "sent_packets", XstatsType::U32, ct::unit_packets_ps),
+    (3, "sent_bytes", XstatsType::U64, ct::unit_bytes_bits_ps),
+    (4, "dropped_packets", XstatsType::U32, ct::unit_packets_ps),
+    (5, "dropped_bytes", XstatsType::U64, ct::unit_bytes_bits_ps),
+    (
+        6,
+        "acks_dropped_packets",
+        XstatsType::U32,
+        ct::unit_packets_ps,
+    ),
+    (
+        7,
+        "acks_dropped_bytes",
+        XstatsType::U64,
+        ct::unit_bytes_bits_ps,
+    ),
+    (
+        8,
+        "ecn_marked_packets",
+        XstatsType::U32,
+        ct::unit_packets_ps,
+    ),
+    (
+        9,
+        "ecn_marked_bytes",
+        XstatsType::U64,
+        ct::unit_bytes_bits_ps,
+    ),
+    (10, "backlog_packets", XstatsType::U32, ct::unit_packets),
+    (11, "backlog_bytes", XstatsType::U32, ct::unit_bytes),
+    (
+        12,
+        "threshold_rate",
+        XstatsType::U64,
+        ct::unit_bytes_bits_rate,
+    ),
+    (13, "target", XstatsType::U32, ct::unit_micro_seconds),
+    (14, "interval", XstatsType::U32, ct::unit_micro_seconds),
+    (15, "way_indirect_hits", XstatsType::U32, ct::unit_units_ps),
+    (16, "way_misses", XstatsType::U32, ct::unit_units_ps),
+    (17, "way_collisions", XstatsType::U32, ct::unit_units_ps),
+    (18, "peak_delay", XstatsType::U32, ct::unit_micro_seconds),
+    (19, "avg_delay", XstatsType::U32, ct::unit_micro_seconds),
+    (20, "base_delay", XstatsType::U32, ct::unit_micro_seconds),
+    (21, "sparse_flows", XstatsType::U32, ct::unit_units),
+    (22, "bulk_flows", XstatsType::U32, ct::unit_units),
+    (23, "unresponsive_flows", XstatsType::U32, ct::unit_units),
+    (24, "max_skblen", XstatsType::U32, ct::unit_bytes),
+    (25, "flow_quantum", XstatsType::U32, ct::unit_bytes),
+];
+
+fn push_xstats_attr(
+    kind: &String,
+    aux: &mut QdiscStatsAux,
+    prefix: &str,
+    nla_type: u16,
+    payload: &[u8],
+    fields: &[XstatsAttr],
+) -> Result<(), DeError> {
+    if let Some((_, name, ty, default_unit)) = fields.iter().find(|(t, _, _, _)| *t == nla_type) {
+        match ty.decode(payload) {
+            Some(value) => {
+                aux.push_counter(kind, &format!("{}{}", prefix, name), value, default_unit())
+            }
+            None => return Err(DeError::new("Unexpected xstats attribute size")),
+        }
+    }
+    Ok(())
+}
+
+struct QdiscAppParserCake {}
+
+impl QdiscAppParser for QdiscAppParserCake {
+    fn parse_app(
+        &self,
+        kind: String,
+        aux: &mut QdiscStatsAux,
+        payload: &Vec<u8>,
+    ) -> Result<(), DeError> {
+        for (nla_type, nla_payload) in nla_attrs(payload) {
+            if nla_type != TCA_CAKE_STATS_TIN_STATS {
+                push_xstats_attr(&kind, aux, "", nla_type, nla_payload, &CAKE_XSTATS)?;
+                continue;
+            }
+            for (tin, tin_payload) in nla_attrs(nla_payload) {
+                let tin = tin
+                    .checked_sub(1)
+                    .ok_or(DeError::new("Cake tin attribute numbered 0"))?;
+                let prefix = format!("tin{}_", tin);
+                for (nla_type, nla_payload) in nla_attrs(tin_payload) {
+                    push_xstats_attr(&kind, aux, &prefix, nla_type, nla_payload, &CAKE_TIN_XSTATS)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// GRED reports per-VQ statistics in TCA_OPTIONS (since 4.19):
+//
+//     TCA_GRED_VQ_LIST
+//       TCA_GRED_VQ_ENTRY
+//         TCA_GRED_VQ_DP, TCA_GRED_VQ_STAT_*
+//       TCA_GRED_VQ_ENTRY
+//         ...
+const TCA_GRED_VQ_LIST: u16 = 6;
+const TCA_GRED_VQ_ENTRY: u16 = 1;
+const TCA_GRED_VQ_DP: u16 = 2;
+static GRED_VQ_XSTATS: [XstatsAttr; 9] = [
+    (3, "bytes", XstatsType::U64, ct::unit_bytes_bits_ps),
+    (4, "packets", XstatsType::U32, ct::unit_packets_ps),
+    (5, "backlog", XstatsType::U32, ct::unit_bytes),
+    (6, "prob_drop", XstatsType::U32, ct::unit_packets_ps),
+    (7, "prob_mark", XstatsType::U32, ct::unit_packets_ps),
+    (8, "forced_drop", XstatsType::U32, ct::unit_packets_ps),
+    (9, "forced_mark", XstatsType::U32, ct::unit_packets_ps),
+    (10, "pdrop", XstatsType::U32, ct::unit_packets_ps),
+    (11, "other", XstatsType::U32, ct::unit_packets_ps),
+];
+
+struct QdiscAppParserGred {}
+
+impl QdiscAppParser for QdiscAppParserGred {
+    fn parse_options(
+        &self,
+        kind: String,
+        aux: &mut QdiscStatsAux,
+        payload: &Vec<u8>,
+    ) -> Result<(), DeError> {
+        for (nla_type, vq_list) in nla_attrs(payload) {
+            if nla_type != TCA_GRED_VQ_LIST {
+                continue;
+            }
+            for (nla_type, vq_entry) in nla_attrs(vq_list) {
+                if nla_type != TCA_GRED_VQ_ENTRY {
+                    continue;
+                }
+                let attrs = nla_attrs(vq_entry);
+                let dp = match attrs
+                    .iter()
+                    .find(|(nla_type, _)| *nla_type == TCA_GRED_VQ_DP)
+                {
+                    Some((_, dp)) => XstatsType::U32
+                        .decode(dp)
+                        .ok_or(DeError::new("Unexpected size of TCA_GRED_VQ_DP"))?,
+                    None => return Err(DeError::new("TCA_GRED_VQ_ENTRY without TCA_GRED_VQ_DP")),
+                };
+
+                aux.vq = Some(dp as u32);
+                for (nla_type, nla_payload) in attrs {
+                    push_xstats_attr(&kind, aux, "", nla_type, nla_payload, &GRED_VQ_XSTATS)?;
+                }
+                aux.vq = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+const QDISC_APP_PARSERS: [(&str, &dyn QdiscAppParser); 12] = [
+    ("red", &QdiscAppParserRed {}),
+    ("gred", &QdiscAppParserGred {}),
+    ("fq_codel", &QdiscAppParserFqCodel {}),
+    ("codel", &QdiscAppParserCodel {}),
+    ("pie", &QdiscAppParserPie {}),
+    ("fq_pie", &QdiscAppParserFqPie {}),
+    ("fq", &QdiscAppParserFq {}),
+    ("cake", &QdiscAppParserCake {}),
+    ("sfq", &QdiscAppParserSfq {}),
+    ("choke", &QdiscAppParserChoke {}),
+    ("htb", &QdiscAppParserHtb {}),
+    ("hfsc", &QdiscAppParserHfsc {}),
+];
+
+fn parse_qdisc_app(
+    kind: &String,
+    aux: &mut QdiscStatsAux,
+    payload: &Vec<u8>,
+) -> Result<(), String> {
+    if let Some((kind, parser)) = QDISC_APP_PARSERS.iter().find(|(a_kind, _)| a_kind == kind) {
+        parser
+            .parse_app(kind.to_string(), aux, payload)
+            .map_err(|err| format!("{} xstats: {}", kind, err))?;
+    }
+    Ok(())
+}
+
+fn parse_qdisc_legacy_stats(
+    kind: &String,
+    aux: &mut QdiscStatsAux,
+    payload: &Vec<u8>,
+) -> Result<(), String> {
+    let mut buf = StreamReadBuffer::new(payload);
+    let tc_stats = TcStats::deserialize(&mut buf).map_err(|err| format!("tc_stats: {}", err))?;
+    aux.push_counter(kind, "bytes", tc_stats.bytes, ct::unit_bytes_bits_ps());
+    aux.push_counter(
+        kind,
+        "packets",
+        tc_stats.packets as u64,
+        ct::unit_packets_ps(),
+    );
+    aux.push_counter(kind, "bps", tc_stats.bps as u64, ct::unit_bytes_bits_ps());
+    aux.push_counter(kind, "pps", tc_stats.pps as u64, ct::unit_packets_ps());
+    aux.push_counter(kind, "qlen", tc_stats.qlen as u64, ct::unit_bytes());
+    aux.push_counter(kind, "backlog", tc_stats.backlog as u64, ct::unit_bytes());
+    aux.push_counter(kind, "drops", tc_stats.drops as u64, ct::unit_packets_ps());
+    aux.push_counter(
+        kind,
+        "overlimits",
+        tc_stats.overlimits as u64,
+        ct::unit_packets_ps(),
+    );
+    Ok(())
+}
+
+fn qdisc_stats(ifname: &str, tcm: Tcmsg) -> Result<Vec<QdiscStat>, String> {
+    let mut aux = QdiscStatsAux::new(ifname, tcm.tcm_handle, tcm.tcm_parent);
+
+    let mut kind: String = "".to_string();
+    // The kernel sends both TCA_STATS2 and the legacy TCA_STATS and TCA_XSTATS. Only use the
+    // legacy ones if the modern ones are missing.
+    let mut have_stats2 = false;
+    let mut have_app = false;
+    let mut legacy_stats = None;
+    let mut legacy_xstats = None;
+    for attr in tcm.rtattrs {
+        match attr.rta_type {
+            Tca::Kind => {
+                kind = String::from_utf8(attr.rta_payload)
+                    .map_err(|err| format!("qdisc kind: {}", err))?;
+                // The string is NUL-terminated, so pop the last char.
+                kind.pop();
+            }
+            Tca::Options => {
+                if let Some((kind, parser)) =
+                    QDISC_APP_PARSERS.iter().find(|(a_kind, _)| *a_kind == kind)
+                {
+                    aux.optional(|aux| {
+                        parser.parse_options(kind.to_string(), aux, &attr.rta_payload)
+                    });
+                }
+            }
+            Tca::Stats2 => {
+                have_stats2 = true;
+                let mut buf = StreamReadBuffer::new(&attr.rta_payload);
+                buf.set_size_hint(attr.payload_size());
+                for nattr in Rtattrs::<TcaStats2, Vec<u8>>::deserialize(&mut buf)
+                    .map_err(|err| format!("{} stats2: {}", kind, err))?
+                {
+                    let mut buf = StreamReadBuffer::new(&nattr.rta_payload);
+                    match nattr.rta_type {
+                        TcaStats2::Basic => {
+                            let gnet_stats = GnetStatsBasic::deserialize(&mut buf)
+                                .map_err(|err| format!("{} stats2: {}", kind, err))?;
+                            aux.push_counter(
+                                &kind,
+                                "bytes",
+                                gnet_stats.bytes,
+                                ct::unit_bytes_bits_ps(),
+                            );
+                            aux.push_counter(
+                                &kind,
+                                "packets",
+                                gnet_stats.packets as u64,
+                                ct::unit_packets_ps(),
+                            );
+                        }
+                        TcaStats2::Queue => {
+                            let gnet_stats = GnetStatsQueue::deserialize(&mut buf)
+                                .map_err(|err| format!("{} stats2: {}", kind, err))?;
+                            aux.push_counter(
+                                &kind,
+                                "qlen",
+                                gnet_stats.qlen as u64,
+                                ct::unit_bytes(),
+                            );
+                            aux.push_counter(
+                                &kind,
+                                "backlog",
+                                gnet_stats.backlog as u64,
+                                ct::unit_bytes(),
+                            );
+                            aux.push_counter(
+                                &kind,
+                                "drops",
+                                gnet_stats.drops as u64,
+                                ct::unit_packets_ps(),
+                            );
+                            aux.push_counter(
+                                &kind,
+                                "requeues",
+                                gnet_stats.requeues as u64,
+                                ct::unit_packets_ps(),
+                            );
+                            aux.push_counter(
+                                &kind,
+                                "overlimits",
+                                gnet_stats.overlimits as u64,
+                                ct::unit_packets_ps(),
+                            );
+                        }
+                        TcaStats2::RateEst => {
+                            let gnet_stats = GnetStatsRateEst::<u32>::deserialize(&mut buf)
+                                .map_err(|err| format!("{} stats2: {}", kind, err))?;
+                            aux.push_counter(
+                                &kind,
+                                "bps",
+                                gnet_stats.bps as u64,
+                                ct::unit_bytes_bits_ps(),
+                            );
+                            aux.push_counter(
+                                &kind,
+                                "pps",
+                                gnet_stats.pps as u64,
+                                ct::unit_packets_ps(),
+                            );
+                        }
+                        TcaStats2::RateEst64 => {
+                            let gnet_stats = GnetStatsRateEst::<u64>::deserialize(&mut buf)
+                                .map_err(|err| format!("{} stats2: {}", kind, err))?;
+                            aux.push_counter(
+                                &kind,
+                                "bps",
+                                gnet_stats.bps,
+                                ct::unit_bytes_bits_ps(),
+                            );
+                            aux.push_counter(&kind, "pps", gnet_stats.pps, ct::unit_packets_ps());
+                        }
+                        TcaStats2::App => {
+                            have_app = true;
+                            aux.optional(|aux| parse_qdisc_app(&kind, aux, &nattr.rta_payload));
+                        }
+
+                        _ => {}
+                    }
+                }
+            }
+            Tca::Stats => {
+                legacy_stats = Some(attr.rta_payload);
+            }
+            Tca::Xstats => {
+                legacy_xstats = Some(attr.rta_payload);
+            }
+            _ => {}
+        }
+    }
+
+    if let (false, Some(payload)) = (have_stats2, legacy_stats) {
+        parse_qdisc_legacy_stats(&kind, &mut aux, &payload)?;
+    }
+    if let (false, Some(payload)) = (have_app, legacy_xstats) {
+        aux.optional(|aux| parse_qdisc_app(&kind, aux, &payload));
+    }
+    Ok(aux.stats)
+}
+
+pub fn qdiscs() -> Result<Vec<QdiscStat>, String> {
     let ifnames = ifindex_map();
     let mut ret = Vec::new();
 
-    let mut socket = NlSocket::connect(NlFamily::Route, None, None, true).unwrap();
+    let mut socket = NlSocket::connect(NlFamily::Route, None, None, true)
+        .map_err(|err| format!("qdiscs: {}", err))?;
     let dump_invisible = Rtattr {
         rta_len: 4,
         rta_type: Tca::DumpInvisible,
@@ -537,7 +1768,9 @@ pub fn qdiscs() -> Vec<QdiscStat> {
         Nlmsghdr::new(len, nl_type, flags, seq, pid, payload)
     };
 
-    socket.send_nl(nlhdr).unwrap();
+    socket
+        .send_nl(nlhdr)
+        .map_err(|err| format!("qdiscs: {}", err))?;
 
     while let Ok(nlmsg) = socket.recv_nl::<u16, Tcmsg>(None) {
         let tcm = nlmsg.nl_payload;
@@ -545,127 +1778,425 @@ pub fn qdiscs() -> Vec<QdiscStat> {
             None => continue,
             Some(name) => name,
         };
-        let mut aux = QdiscStatsAux::new(ifname, tcm.tcm_handle, tcm.tcm_parent);
-
-        let mut kind: String = "".to_string();
-        for attr in tcm.rtattrs {
-            match attr.rta_type {
-                Tca::Kind => {
-                    kind = std::str::from_utf8(&attr.rta_payload).unwrap().to_string();
-                    // The string is NUL-terminated, so pop the last char.
-                    kind.pop();
-                }
-                Tca::Stats2 => {
-                    let mut buf = StreamReadBuffer::new(&attr.rta_payload);
-                    buf.set_size_hint(attr.payload_size());
-                    for nattr in Rtattrs::<TcaStats2, Vec<u8>>::deserialize(&mut buf).unwrap() {
-                        let mut buf = StreamReadBuffer::new(&nattr.rta_payload);
-                        match nattr.rta_type {
-                            TcaStats2::Basic => {
-                                let gnet_stats = GnetStatsBasic::deserialize(&mut buf).unwrap();
-                                aux.push_counter(
-                                    &kind,
-                                    "bytes",
-                                    gnet_stats.bytes,
-                                    ct::unit_bytes_bits_ps(),
-                                );
-                                aux.push_counter(
-                                    &kind,
-                                    "packets",
-                                    gnet_stats.packets as u64,
-                                    ct::unit_packets_ps(),
-                                );
-                            }
-                            TcaStats2::Queue => {
-                                let gnet_stats = GnetStatsQueue::deserialize(&mut buf).unwrap();
-                                aux.push_counter(
-                                    &kind,
-                                    "qlen",
-                                    gnet_stats.qlen as u64,
-                                    ct::unit_bytes(),
-                                );
-                                aux.push_counter(
-                                    &kind,
-                                    "backlog",
-                                    gnet_stats.backlog as u64,
-                                    ct::unit_bytes(),
-                                );
-                                aux.push_counter(
-                                    &kind,
-                                    "drops",
-                                    gnet_stats.drops as u64,
-                                    ct::unit_packets_ps(),
-                                );
-                                aux.push_counter(
-                                    &kind,
-                                    "requeues",
-                                    gnet_stats.requeues as u64,
-                                    ct::unit_packets_ps(),
-                                );
-                                aux.push_counter(
-                                    &kind,
-                                    "overlimits",
-                                    gnet_stats.overlimits as u64,
-                                    ct::unit_packets_ps(),
-                                );
-                            }
-                            TcaStats2::RateEst => {
-                                let gnet_stats =
-                                    GnetStatsRateEst::<u32>::deserialize(&mut buf).unwrap();
-                                aux.push_counter(
-                                    &kind,
-                                    "bps",
-                                    gnet_stats.bps as u64,
-                                    ct::unit_bytes_bits_ps(),
-                                );
-                                aux.push_counter(
-                                    &kind,
-                                    "pps",
-                                    gnet_stats.pps as u64,
-                                    ct::unit_packets_ps(),
-                                );
-                            }
-                            TcaStats2::RateEst64 => {
-                                let gnet_stats =
-                                    GnetStatsRateEst::<u64>::deserialize(&mut buf).unwrap();
-                                aux.push_counter(
-                                    &kind,
-                                    "bps",
-                                    gnet_stats.bps,
-                                    ct::unit_bytes_bits_ps(),
-                                );
-                                aux.push_counter(
-                                    &kind,
-                                    "pps",
-                                    gnet_stats.pps,
-                                    ct::unit_packets_ps(),
-                                );
-                            }
-                            TcaStats2::App => {
-                                if let Some((kind, parser)) =
-                                    QDISC_APP_PARSERS.iter().find(|(a_kind, _)| *a_kind == kind)
-                                {
-                                    parser.parse_app(
-                                        kind.to_string(),
-                                        &mut aux,
-                                        &nattr.rta_payload,
-                                    );
-                                }
-                            }
-
-                            _ => {}
-                        }
-                    }
-                }
-                Tca::Stats | Tca::Xstats => {
-                    // xxx I think Stats is backward-compat combination of TcaStats2::Basic
-                    // xxx and TcaStats2::Queue and Xstats backward-compat TcaStats2::App.
-                }
-                _ => {}
-            }
+        ret.extend(qdisc_stats(ifname, tcm)?);
+    }
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|v| v.to_ne_bytes().to_vec())
+            .collect()
+    }
+
+    fn u64s(values: &[u64]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|v| v.to_ne_bytes().to_vec())
+            .collect()
+    }
+
+    fn nla(nla_type: u16, payload: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        nla_put(&mut buf, nla_type, payload);
+        buf
+    }
+
+    fn names_values(mut aux: QdiscStatsAux) -> Vec<(String, u64)> {
+        aux.stats
+            .drain(..)
+            .map(|stat| (stat.name, stat.value))
+            .collect()
+    }
+
+    fn app_stats(kind: &str, payload: Vec<u8>) -> Result<Vec<(String, u64)>, String> {
+        let mut aux = QdiscStatsAux::new("eth0", 0x10000, 0xffffffff);
+        parse_qdisc_app(&kind.to_string(), &mut aux, &payload)?;
+        Ok(names_values(aux))
+    }
+
+    fn expect(stats: Vec<(String, u64)>, expected: &[(&str, u64)]) {
+        let expected: Vec<(String, u64)> = expected
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect();
+        assert_eq!(stats, expected);
+    }
+
+    #[test]
+    fn optional_part() {
+        let mut aux = QdiscStatsAux::new("eth0", 0x10000, 0xffffffff);
+        aux.push_counter(&"red".to_string(), "bytes", 100, ct::unit_bytes());
+        aux.optional(|aux| parse_qdisc_app(&"red".to_string(), aux, &u32s(&[1, 2])));
+        aux.optional(|aux| {
+            aux.push_counter(&"red".to_string(), "early", 1, ct::unit_packets_ps());
+            Err(())
+        });
+        expect(names_values(aux), &[("bytes", 100)]);
+    }
+
+    #[test]
+    fn red() {
+        let stats = app_stats("red", u32s(&[1, 2, 3, 4])).unwrap();
+        expect(
+            stats,
+            &[("early", 1), ("pdrop", 2), ("other", 3), ("marked", 4)],
+        );
+        assert!(app_stats("red", u32s(&[1, 2])).is_err());
+    }
+
+    #[test]
+    fn gred() {
+        let mut entry = nla(TCA_GRED_VQ_DP, &u32s(&[3]));
+        entry.extend(nla(3, &u64s(&[1000])));
+        entry.extend(nla(4, &u32s(&[10])));
+        entry.extend(nla(10, &u32s(&[2])));
+        let payload = nla(TCA_GRED_VQ_LIST, &nla(TCA_GRED_VQ_ENTRY, &entry));
+
+        let mut aux = QdiscStatsAux::new("eth0", 0x10000, 0xffffffff);
+        QdiscAppParserGred {}
+            .parse_options("gred".to_string(), &mut aux, &payload)
+            .unwrap();
+        assert!(aux.stats.iter().all(|stat| stat.vq == Some(3)));
+        expect(
+            names_values(aux),
+            &[("bytes", 1000), ("packets", 10), ("pdrop", 2)],
+        );
+
+        let payload = nla(
+            TCA_GRED_VQ_LIST,
+            &nla(TCA_GRED_VQ_ENTRY, &nla(4, &u32s(&[10]))),
+        );
+        let mut aux = QdiscStatsAux::new("eth0", 0x10000, 0xffffffff);
+        assert!(QdiscAppParserGred {}
+            .parse_options("gred".to_string(), &mut aux, &payload)
+            .is_err());
+    }
+
+    #[test]
+    fn fq_codel() {
+        let mut payload = u32s(&[TCA_FQ_CODEL_XSTATS_QDISC]);
+        payload.extend(u32s(&[1514, 1, 2, 3, 4, 5, 6, 7, 8]));
+        expect(
+            app_stats("fq_codel", payload).unwrap(),
+            &[
+                ("maxpacket", 1514),
+                ("drop_overlimit", 1),
+                ("ecn_mark", 2),
+                ("new_flow_count", 3),
+                ("new_flows_len", 4),
+                ("old_flows_len", 5),
+                ("ce_mark", 6),
+                ("memory_usage", 7),
+                ("drop_overmemory", 8),
+            ],
+        );
+
+        let mut payload = u32s(&[TCA_FQ_CODEL_XSTATS_CLASS]);
+        payload.extend(u32s(&[(-100i32) as u32, 1, 2, 3, 4, 5]));
+        expect(
+            app_stats("fq_codel", payload).unwrap(),
+            &[
+                ("deficit", 0),
+                ("ldelay", 1),
+                ("count", 2),
+                ("lastcount", 3),
+                ("dropping", 4),
+                ("drop_next", 5),
+            ],
+        );
+
+        assert!(app_stats("fq_codel", vec![0, 0]).is_err());
+    }
+
+    #[test]
+    fn codel() {
+        expect(
+            app_stats("codel", u32s(&[1514, 1, 2, 3, 4, 5, 6, 7, 8])).unwrap(),
+            &[
+                ("maxpacket", 1514),
+                ("count", 1),
+                ("lastcount", 2),
+                ("ldelay", 3),
+                ("drop_next", 4),
+                ("drop_overlimit", 5),
+                ("ecn_mark", 6),
+                ("dropping", 7),
+                ("ce_mark", 8),
+            ],
+        );
+    }
+
+    #[test]
+    fn pie() {
+        let mut payload = u64s(&[1 << 40]);
+        payload.extend(u32s(&[1, 2, 3, 4, 5, 6, 7, 8]));
+        expect(
+            app_stats("pie", payload).unwrap(),
+            &[
+                ("prob", 1 << 40),
+                ("delay", 1),
+                ("avg_dq_rate", 2),
+                ("dq_rate_estimating", 3),
+                ("packets_in", 4),
+                ("dropped", 5),
+                ("overlimit", 6),
+                ("maxq", 7),
+                ("ecn_mark", 8),
+            ],
+        );
+
+        // Before 5.7.
+        expect(
+            app_stats("pie", u32s(&[9, 1, 2, 3, 4, 5, 6, 7])).unwrap(),
+            &[
+                ("prob", 9),
+                ("delay", 1),
+                ("avg_dq_rate", 2),
+                ("packets_in", 3),
+                ("dropped", 4),
+                ("overlimit", 5),
+                ("maxq", 6),
+                ("ecn_mark", 7),
+            ],
+        );
+    }
+
+    #[test]
+    fn fq_pie() {
+        expect(
+            app_stats("fq_pie", u32s(&[1, 2, 3, 4, 5, 6, 7, 8, 9])).unwrap(),
+            &[
+                ("packets_in", 1),
+                ("dropped", 2),
+                ("overlimit", 3),
+                ("overmemory", 4),
+                ("ecn_mark", 5),
+                ("new_flow_count", 6),
+                ("new_flows_len", 7),
+                ("old_flows_len", 8),
+                ("memory_usage", 9),
+            ],
+        );
+    }
+
+    #[test]
+    fn fq() {
+        // An older kernel that stops after unthrottle_latency_ns.
+        let mut payload = u64s(&[1, 2, 3, 4, 5, 6, 7, (-1i64) as u64]);
+        payload.extend(u32s(&[8, 9, 10, 11]));
+        let stats = app_stats("fq", payload).unwrap();
+        assert_eq!(stats.len(), 12);
+        assert_eq!(stats[0], ("gc_flows".to_string(), 1));
+        assert_eq!(stats[7], ("time_next_delayed_flow".to_string(), 0));
+        assert_eq!(stats[11], ("unthrottle_latency_ns".to_string(), 11));
+
+        assert!(app_stats("fq", u32s(&[1])).is_err());
+    }
+
+    #[test]
+    fn cake() {
+        let mut tin = nla(2, &u32s(&[100]));
+        tin.extend(nla(3, &u64s(&[150000])));
+        tin.extend(nla(11, &u32s(&[3000])));
+        let mut tins = nla(1, &tin);
+        tins.extend(nla(2, &nla(4, &u32s(&[7]))));
+
+        let mut payload = nla(2, &u64s(&[12500000]));
+        payload.extend(nla(4, &u32s(&[4096])));
+        payload.extend(nla(TCA_CAKE_STATS_TIN_STATS, &tins));
+        payload.extend(nla(11, &u32s(&[1514])));
+        expect(
+            app_stats("cake", payload).unwrap(),
+            &[
+                ("capacity_estimate", 12500000),
+                ("memory_used", 4096),
+                ("tin0_sent_packets", 100),
+                ("tin0_sent_bytes", 150000),
+                ("tin0_backlog_bytes", 3000),
+                ("tin1_dropped_packets", 7),
+                ("deficit", 1514),
+            ],
+        );
+
+        // Wrong attribute size.
+        assert!(app_stats("cake", nla(3, &u64s(&[1]))).is_err());
+        // Tins are numbered from 1.
+        let payload = nla(TCA_CAKE_STATS_TIN_STATS, &nla(0, &nla(2, &u32s(&[1]))));
+        assert!(app_stats("cake", payload).is_err());
+    }
+
+    #[test]
+    fn sfq() {
+        expect(app_stats("sfq", u32s(&[1514])).unwrap(), &[("allot", 1514)]);
+    }
+
+    #[test]
+    fn choke() {
+        expect(
+            app_stats("choke", u32s(&[1, 2, 3, 4, 5])).unwrap(),
+            &[
+                ("early", 1),
+                ("pdrop", 2),
+                ("other", 3),
+                ("marked", 4),
+                ("matched", 5),
+            ],
+        );
+    }
+
+    #[test]
+    fn htb() {
+        expect(
+            app_stats("htb", u32s(&[1, 2, 3, 400, (-5i32) as u32])).unwrap(),
+            &[
+                ("lends", 1),
+                ("borrows", 2),
+                ("giants", 3),
+                ("tokens", 400),
+                ("ctokens", 0),
+            ],
+        );
+    }
+
+    #[test]
+    fn hfsc() {
+        let mut payload = u64s(&[1000, 2000]);
+        payload.extend(u32s(&[3, 1]));
+        expect(
+            app_stats("hfsc", payload).unwrap(),
+            &[
+                ("work", 1000),
+                ("rtwork", 2000),
+                ("period", 3),
+                ("level", 1),
+            ],
+        );
+    }
+
+    #[test]
+    fn unknown_kind() {
+        assert_eq!(app_stats("noqueue", u32s(&[1, 2])).unwrap(), vec![]);
+    }
+
+    fn tcmsg(attrs: Vec<(Tca, Vec<u8>)>) -> Tcmsg {
+        Tcmsg {
+            tcm_family: 0,
+            tcm_ifindex: 0,
+            tcm_handle: 0x10000,
+            tcm_parent: 0xffffffff,
+            tcm_info: 0,
+            rtattrs: Rtattrs::new(
+                attrs
+                    .into_iter()
+                    .map(|(rta_type, payload)| Rtattr {
+                        rta_len: 4 + payload.len() as libc::c_ushort,
+                        rta_type: rta_type,
+                        rta_payload: payload,
+                    })
+                    .collect(),
+            ),
         }
-        ret.extend(aux.stats.drain(..));
     }
 
-    return ret;
+    // struct tc_stats: bytes, then packets, drops, overlimits, bps, pps, qlen and backlog.
+    fn tc_stats() -> Vec<u8> {
+        let mut payload = u64s(&[1000]);
+        payload.extend(u32s(&[10, 1, 2, 3, 4, 5, 6]));
+        payload
+    }
+
+    #[test]
+    fn legacy_stats() {
+        let mut aux = QdiscStatsAux::new("eth0", 0x10000, 0xffffffff);
+        parse_qdisc_legacy_stats(&"red".to_string(), &mut aux, &tc_stats()).unwrap();
+        expect(
+            names_values(aux),
+            &[
+                ("bytes", 1000),
+                ("packets", 10),
+                ("bps", 3),
+                ("pps", 4),
+                ("qlen", 5),
+                ("backlog", 6),
+                ("drops", 1),
+                ("overlimits", 2),
+            ],
+        );
+
+        let mut aux = QdiscStatsAux::new("eth0", 0x10000, 0xffffffff);
+        assert!(parse_qdisc_legacy_stats(&"red".to_string(), &mut aux, &u32s(&[1, 2])).is_err());
+    }
+
+    #[test]
+    fn legacy_fallback() {
+        // Only TCA_STATS and TCA_XSTATS, as from an old kernel.
+        let stats = qdisc_stats(
+            "eth0",
+            tcmsg(vec![
+                (Tca::Kind, b"red\0".to_vec()),
+                (Tca::Stats, tc_stats()),
+                (Tca::Xstats, u32s(&[7, 8, 9, 10])),
+            ]),
+        )
+        .unwrap();
+        let names_values: Vec<(String, u64)> = stats
+            .into_iter()
+            .map(|stat| (stat.name, stat.value))
+            .collect();
+        expect(
+            names_values,
+            &[
+                ("bytes", 1000),
+                ("packets", 10),
+                ("bps", 3),
+                ("pps", 4),
+                ("qlen", 5),
+                ("backlog", 6),
+                ("drops", 1),
+                ("overlimits", 2),
+                ("early", 7),
+                ("pdrop", 8),
+                ("other", 9),
+                ("marked", 10),
+            ],
+        );
+
+        // With TCA_STATS2 and TCA_STATS_APP present, the legacy attributes are ignored.
+        let mut basic = u64s(&[500]);
+        basic.extend(u32s(&[5, 0]));
+        let mut stats2 = nla(1, &basic);
+        stats2.extend(nla(4, &u32s(&[1, 2, 3, 4])));
+        let stats = qdisc_stats(
+            "eth0",
+            tcmsg(vec![
+                (Tca::Kind, b"red\0".to_vec()),
+                (Tca::Stats2, stats2),
+                (Tca::Stats, tc_stats()),
+                (Tca::Xstats, u32s(&[7, 8, 9, 10])),
+            ]),
+        )
+        .unwrap();
+        let names_values: Vec<(String, u64)> = stats
+            .into_iter()
+            .map(|stat| (stat.name, stat.value))
+            .collect();
+        expect(
+            names_values,
+            &[
+                ("bytes", 500),
+                ("packets", 5),
+                ("early", 1),
+                ("pdrop", 2),
+                ("other", 3),
+                ("marked", 4),
+            ],
+        );
+    }
 }
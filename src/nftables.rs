@@ -0,0 +1,191 @@
+use crate::ct;
+use crate::netlink;
+
+const NFNL_SUBSYS_NFTABLES: u8 = 10;
+
+const NFT_MSG_GETRULE: u8 = 7;
+const NFT_MSG_GETOBJ: u8 = 19;
+
+const NFPROTO_UNSPEC: u8 = 0;
+
+const NFTA_RULE_TABLE: u16 = 1;
+const NFTA_RULE_CHAIN: u16 = 2;
+const NFTA_RULE_HANDLE: u16 = 3;
+const NFTA_RULE_EXPRESSIONS: u16 = 4;
+const NFTA_RULE_USERDATA: u16 = 7;
+
+const NFTA_LIST_ELEM: u16 = 1;
+
+const NFTA_EXPR_NAME: u16 = 1;
+const NFTA_EXPR_DATA: u16 = 2;
+
+const NFTA_COUNTER_BYTES: u16 = 1;
+const NFTA_COUNTER_PACKETS: u16 = 2;
+
+const NFTA_OBJ_TABLE: u16 = 1;
+const NFTA_OBJ_NAME: u16 = 2;
+const NFTA_OBJ_TYPE: u16 = 3;
+const NFTA_OBJ_DATA: u16 = 4;
+
+const NFT_OBJECT_COUNTER: u32 = 1;
+
+// The rule comment is stored by libnftnl in NFTA_RULE_USERDATA as a TLV with 8-bit type and
+// length.
+const NFTNL_UDATA_RULE_COMMENT: u8 = 0;
+
+#[derive(Clone, Debug)]
+pub struct NftStat {
+    pub family: String,
+    pub table: String,
+    pub chain: Option<String>,
+    pub rule: Option<String>,
+    pub obj: Option<String>,
+    pub name: String,
+    pub value: u64,
+    pub default_unit: ct::UnitChain,
+}
+
+fn family_name(family: u8) -> String {
+    match family {
+        1 => "inet".to_string(),
+        2 => "ip".to_string(),
+        3 => "arp".to_string(),
+        5 => "netdev".to_string(),
+        7 => "bridge".to_string(),
+        10 => "ip6".to_string(),
+        _ => family.to_string(),
+    }
+}
+
+fn rule_comment(mut udata: &[u8]) -> Option<String> {
+    while udata.len() >= 2 {
+        let (udata_type, len) = (udata[0], udata[1] as usize);
+        if 2 + len > udata.len() {
+            break;
+        }
+        if udata_type == NFTNL_UDATA_RULE_COMMENT {
+            return Some(netlink::nla_string(&udata[2..2 + len]));
+        }
+        udata = &udata[2 + len..];
+    }
+    None
+}
+
+// Returns (packets, bytes) of a counter expression or object.
+fn counter_data(data: &[u8]) -> Result<(u64, u64), String> {
+    let attrs = netlink::nla_attrs(data);
+    match (
+        netlink::nla_find(&attrs, NFTA_COUNTER_PACKETS).and_then(netlink::nla_be64),
+        netlink::nla_find(&attrs, NFTA_COUNTER_BYTES).and_then(netlink::nla_be64),
+    ) {
+        (Some(packets), Some(bytes)) => Ok((packets, bytes)),
+        _ => Err("Malformed nftables counter".to_string()),
+    }
+}
+
+// Push packets and bytes of one counter. `key` has everything filled in except the counter itself.
+fn push_counter(
+    stats: &mut Vec<NftStat>,
+    key: &NftStat,
+    suffix: &str,
+    (packets, bytes): (u64, u64),
+) {
+    for (name, value, default_unit) in vec![
+        ("packets", packets, ct::unit_packets_ps()),
+        ("bytes", bytes, ct::unit_bytes_bits_ps()),
+    ] {
+        stats.push(NftStat {
+            name: format!("{}{}", name, suffix),
+            value: value,
+            default_unit: default_unit,
+            ..key.clone()
+        });
+    }
+}
+
+// Counters of all nftables rules, and all named counter objects. Rules are identified by their
+// comment if they have one, otherwise by handle.
+pub fn stats() -> Result<Vec<NftStat>, String> {
+    let mut stats = Vec::new();
+
+    for msg in netlink::nfnl_dump(NFNL_SUBSYS_NFTABLES, NFT_MSG_GETRULE, NFPROTO_UNSPEC)? {
+        let attrs = netlink::nla_attrs(&msg.attrs);
+        let table = netlink::nla_find(&attrs, NFTA_RULE_TABLE)
+            .map(netlink::nla_string)
+            .unwrap_or_default();
+        let chain = netlink::nla_find(&attrs, NFTA_RULE_CHAIN).map(netlink::nla_string);
+        let rule = match netlink::nla_find(&attrs, NFTA_RULE_USERDATA).and_then(rule_comment) {
+            Some(comment) => Some(comment),
+            None => netlink::nla_find(&attrs, NFTA_RULE_HANDLE)
+                .and_then(netlink::nla_be64)
+                .map(|handle| handle.to_string()),
+        };
+        let key = NftStat {
+            family: family_name(msg.family),
+            table: table,
+            chain: chain,
+            rule: rule,
+            obj: None,
+            name: String::new(),
+            value: 0,
+            default_unit: ct::unit_units(),
+        };
+        let exprs = match netlink::nla_find(&attrs, NFTA_RULE_EXPRESSIONS) {
+            Some(exprs) => exprs,
+            None => continue,
+        };
+
+        // A rule can have several counter expressions. The first one gives packets and bytes, the
+        // following ones packets1, bytes1 etc.
+        let mut nth = 0;
+        for (nla_type, expr) in netlink::nla_attrs(exprs) {
+            if nla_type != NFTA_LIST_ELEM {
+                continue;
+            }
+            let expr_attrs = netlink::nla_attrs(expr);
+            match netlink::nla_find(&expr_attrs, NFTA_EXPR_NAME).map(netlink::nla_string) {
+                Some(ref name) if name == "counter" => {}
+                _ => continue,
+            }
+            if let Some(data) = netlink::nla_find(&expr_attrs, NFTA_EXPR_DATA) {
+                let suffix = if nth == 0 {
+                    String::new()
+                } else {
+                    nth.to_string()
+                };
+                push_counter(&mut stats, &key, &suffix, counter_data(data)?);
+                nth += 1;
+            }
+        }
+    }
+
+    for msg in netlink::nfnl_dump(NFNL_SUBSYS_NFTABLES, NFT_MSG_GETOBJ, NFPROTO_UNSPEC)? {
+        let attrs = netlink::nla_attrs(&msg.attrs);
+        if netlink::nla_find(&attrs, NFTA_OBJ_TYPE).and_then(netlink::nla_be32)
+            != Some(NFT_OBJECT_COUNTER)
+        {
+            continue;
+        }
+        let table = netlink::nla_find(&attrs, NFTA_OBJ_TABLE)
+            .map(netlink::nla_string)
+            .unwrap_or_default();
+        let obj = netlink::nla_find(&attrs, NFTA_OBJ_NAME)
+            .map(netlink::nla_string)
+            .unwrap_or_default();
+        let key = NftStat {
+            family: family_name(msg.family),
+            table: table,
+            chain: None,
+            rule: None,
+            obj: Some(obj),
+            name: String::new(),
+            value: 0,
+            default_unit: ct::unit_units(),
+        };
+        if let Some(data) = netlink::nla_find(&attrs, NFTA_OBJ_DATA) {
+            push_counter(&mut stats, &key, "", counter_data(data)?);
+        }
+    }
+
+    Ok(stats)
+}
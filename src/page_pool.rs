@@ -0,0 +1,78 @@
+use crate::ct;
+use crate::netlink;
+
+const NETDEV_GENL_NAME: &str = "netdev";
+const NETDEV_GENL_VERSION: u8 = 1;
+
+const NETDEV_CMD_PAGE_POOL_STATS_GET: u8 = 9;
+
+const NETDEV_A_PAGE_POOL_ID: u16 = 1;
+const NETDEV_A_PAGE_POOL_IFINDEX: u16 = 2;
+
+const NETDEV_A_PAGE_POOL_STATS_INFO: u16 = 1;
+
+// NETDEV_A_PAGE_POOL_STATS_*. Numbering of the counters starts at 8.
+static PAGE_POOL_STATS: [(u16, &str); 11] = [
+    (8, "alloc_fast"),
+    (9, "alloc_slow"),
+    (10, "alloc_slow_high_order"),
+    (11, "alloc_empty"),
+    (12, "alloc_refill"),
+    (13, "alloc_waive"),
+    (14, "recycle_cached"),
+    (15, "recycle_cache_full"),
+    (16, "recycle_ring"),
+    (17, "recycle_ring_full"),
+    (18, "recycle_released_refcnt"),
+];
+
+#[derive(Debug)]
+pub struct PagePoolStat {
+    pub id: u64,
+    pub ifname: String,
+    pub name: String,
+    pub value: u64,
+    pub default_unit: ct::UnitChain,
+}
+
+// Statistics of all page pools. Pools that are not associated with a netdevice (or are associated
+// with the loopback) have no ifindex, and are shown with ifname "-".
+pub fn stats() -> Result<Vec<PagePoolStat>, String> {
+    let ifnames = netlink::ifindex_map();
+    let mut stats = Vec::new();
+
+    for msg in netlink::genl_dump(
+        NETDEV_GENL_NAME,
+        NETDEV_GENL_VERSION,
+        NETDEV_CMD_PAGE_POOL_STATS_GET,
+        Vec::new(),
+    )? {
+        let attrs = netlink::nla_attrs(&msg.attrs);
+        let info = match netlink::nla_find(&attrs, NETDEV_A_PAGE_POOL_STATS_INFO) {
+            Some(info) => netlink::nla_attrs(info),
+            None => continue,
+        };
+        let id = netlink::nla_find(&info, NETDEV_A_PAGE_POOL_ID)
+            .and_then(netlink::nla_uint)
+            .ok_or("Page pool stats without a pool ID")?;
+        let ifname = netlink::nla_find(&info, NETDEV_A_PAGE_POOL_IFINDEX)
+            .and_then(netlink::nla_u32)
+            .and_then(|ifindex| ifnames.get(&(ifindex as libc::c_int)))
+            .cloned()
+            .unwrap_or_else(|| "-".to_string());
+
+        for (nla_type, name) in PAGE_POOL_STATS.iter() {
+            if let Some(value) = netlink::nla_find(&attrs, *nla_type).and_then(netlink::nla_uint) {
+                stats.push(PagePoolStat {
+                    id: id,
+                    ifname: ifname.clone(),
+                    name: name.to_string(),
+                    value: value,
+                    default_unit: ct::unit_units_ps(),
+                });
+            }
+        }
+    }
+
+    Ok(stats)
+}
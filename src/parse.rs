@@ -1,9 +1,27 @@
+use crate::conntrack;
 use crate::ct;
+use crate::devlink;
 use crate::ethtool_ss;
+use crate::exec;
+use crate::file;
+use crate::inet_diag;
+use crate::interrupts;
 use crate::netlink;
-
+use crate::nftables;
+use crate::page_pool;
+use crate::rdma;
+use crate::snmp;
+use crate::softnet;
+use crate::xdp;
+
+use std::collections::HashMap;
 use std::iter::Peekable;
+use std::net::IpAddr;
+use std::os::unix::fs::MetadataExt;
+use std::rc::Rc;
 
+// On error, `words` is left at the word that failed, so that the error can point at it. If the
+// expression ended early, there is no word left.
 trait Parser {
     fn parse(
         &self,
@@ -11,6 +29,90 @@ trait Parser {
     ) -> Result<Vec<Box<dyn ct::CounterRule>>, String>;
 }
 
+// An error in the expression. `word` is the index of the offending word, `offset` its byte offset
+// in the words joined by spaces. Both point past the last word if the expression ended early.
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub word: usize,
+    pub offset: usize,
+    pub suggestion: Option<String>,
+}
+
+impl ParseError {
+    fn new(words: &[String], word: usize, message: String) -> ParseError {
+        ParseError {
+            message: message,
+            word: word,
+            offset: words.iter().take(word).map(|word| word.len() + 1).sum(),
+            suggestion: None,
+        }
+    }
+
+    fn suggest(mut self, words: &[String], candidates: &[String]) -> ParseError {
+        self.suggestion = words
+            .get(self.word)
+            .and_then(|word| closest(word, candidates))
+            .cloned();
+        self
+    }
+
+    // The expression, and a line that points at the offending word.
+    pub fn show(&self, words: &[String]) -> String {
+        let width = words.get(self.word).map(|word| word.len()).unwrap_or(1);
+        format!(
+            "{}\n{}{}",
+            words.join(" "),
+            " ".repeat(self.offset),
+            "^".repeat(std::cmp::max(width, 1))
+        )
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.suggestion {
+            Some(suggestion) => write!(f, "{}, did you mean `{}`?", self.message, suggestion),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+// Edit distance, where swapping two adjacent characters counts as one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in 0..=a.len() {
+        for j in 0..=b.len() {
+            d[i][j] = if i == 0 || j == 0 {
+                i + j
+            } else {
+                let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+                let mut best = std::cmp::min(
+                    d[i - 1][j - 1] + cost,
+                    std::cmp::min(d[i - 1][j], d[i][j - 1]) + 1,
+                );
+                if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                    best = std::cmp::min(best, d[i - 2][j - 2] + 1);
+                }
+                best
+            };
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+// The candidate closest to a word that is not the word itself, if it is close enough to be a typo.
+fn closest<'a>(word: &str, candidates: &'a [String]) -> Option<&'a String> {
+    let limit = std::cmp::min(std::cmp::max(word.len() / 4, 1), 3);
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|(distance, _)| *distance > 0 && *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
 fn is_ns(word: &String) -> bool {
     if let Some(last) = word.chars().last() {
         last == ':'
@@ -39,6 +141,81 @@ fn parse_ns_opt(words: &mut Peekable<std::slice::Iter<String>>) -> Option<String
     }
 }
 
+// A name is matched either by a glob, or by a regular expression, written as ~regex. Regexes
+// are not anchored. Their capture groups become key columns $1, $2 etc.
+#[derive(Debug)]
+enum NamePattern {
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+}
+
+impl NamePattern {
+    fn new(word: &str) -> Result<NamePattern, String> {
+        if word.starts_with('~') {
+            let re = regex::Regex::new(&word[1..]).map_err(|err| err.to_string())?;
+            if re.captures_len() - 1 > ct::MAX_CAPTURES {
+                return Err(format!("Too many capture groups: {}", word));
+            }
+            Ok(NamePattern::Regex(re))
+        } else {
+            glob::Pattern::new(word)
+                .map(NamePattern::Glob)
+                .map_err(|err| err.msg.to_string())
+        }
+    }
+
+    fn any() -> NamePattern {
+        NamePattern::Glob(glob::Pattern::new("*").unwrap())
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            NamePattern::Glob(pat) => pat.matches(name),
+            NamePattern::Regex(re) => re.is_match(name),
+        }
+    }
+
+    // Groups that did not participate in the match capture an empty string.
+    fn captures(&self, name: &str) -> Vec<String> {
+        match self {
+            NamePattern::Glob(_) => Vec::new(),
+            NamePattern::Regex(re) => match re.captures(name) {
+                Some(caps) => caps
+                    .iter()
+                    .skip(1)
+                    .map(|cap| cap.map_or("", |cap| cap.as_str()).to_string())
+                    .collect(),
+                None => Vec::new(),
+            },
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            NamePattern::Glob(pat) => pat.as_str(),
+            NamePattern::Regex(re) => re.as_str(),
+        }
+    }
+}
+
+// Captures are numbered in the order they are added to the key.
+fn push_captures(key: &mut Vec<(ct::KeyHead, String)>, captures: &Vec<String>) {
+    let mut n = key
+        .iter()
+        .filter(|(head, _)| match head {
+            ct::KeyHead::Capture(_) => true,
+            _ => false,
+        })
+        .count();
+    for capture in captures {
+        if n >= ct::MAX_CAPTURES {
+            break;
+        }
+        key.push((ct::KeyHead::Capture(n), capture.clone()));
+        n += 1;
+    }
+}
+
 fn is_ifmatch(word: &String) -> bool {
     if let Some(first) = word.chars().nth(0) {
         first == '@'
@@ -47,15 +224,53 @@ fn is_ifmatch(word: &String) -> bool {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+enum IfAttr {
+    Name,
+    Driver,
+    Master,
+    Kind,
+    Parentdev,
+    Altname,
+}
+
+static IF_ATTRS: [(&str, IfAttr); 5] = [
+    ("driver", IfAttr::Driver),
+    ("master", IfAttr::Master),
+    ("kind", IfAttr::Kind),
+    ("parentdev", IfAttr::Parentdev),
+    ("altname", IfAttr::Altname),
+];
+
+// An interface match. Interfaces are selected by name, @glob, or by an attribute, e.g.
+// @kind=vxlan. Exclusions, written as @!glob, remove interfaces that the matches before them let
+// through.
+#[derive(Debug)]
+struct IfnameMatch {
+    attr: IfAttr,
+    pat: NamePattern,
+    exclude: bool,
+}
+
 fn peek_ifmatch(
     words: &mut Peekable<std::slice::Iter<String>>,
-) -> Result<Option<glob::Pattern>, glob::PatternError> {
+) -> Result<Option<IfnameMatch>, String> {
     if let Some(word) = words.peek() {
         if is_ifmatch(word) {
-            return Some(glob::Pattern::new(
-                &(*word).chars().skip(1).collect::<String>(),
-            ))
-            .transpose();
+            let exclude = word.chars().nth(1) == Some('!');
+            let mut spec = &word[if exclude { 2 } else { 1 }..];
+            let mut attr = IfAttr::Name;
+            for (name, if_attr) in IF_ATTRS.iter() {
+                if spec.starts_with(name) && spec[name.len()..].starts_with('=') {
+                    spec = &spec[name.len() + 1..];
+                    attr = if_attr.clone();
+                }
+            }
+            return Ok(Some(IfnameMatch {
+                attr: attr,
+                pat: NamePattern::new(spec)?,
+                exclude: exclude,
+            }));
         }
     }
     Ok(None)
@@ -63,7 +278,7 @@ fn peek_ifmatch(
 
 fn parse_ifmatch(
     words: &mut Peekable<std::slice::Iter<String>>,
-) -> Result<Option<glob::Pattern>, glob::PatternError> {
+) -> Result<Option<IfnameMatch>, String> {
     if let Some(ifmatch) = peek_ifmatch(words)? {
         words.next();
         Ok(Some(ifmatch))
@@ -72,6 +287,87 @@ fn parse_ifmatch(
     }
 }
 
+fn any_ifname() -> IfnameMatch {
+    IfnameMatch {
+        attr: IfAttr::Name,
+        pat: NamePattern::any(),
+        exclude: false,
+    }
+}
+
+// Evaluates interface matches against interface names. Attributes of interfaces are only dumped
+// if a match needs them.
+struct IfnameMatcher<'a> {
+    ifmatches: &'a Vec<IfnameMatch>,
+    links: Rc<HashMap<String, netlink::LinkAttrs>>,
+}
+
+impl<'a> IfnameMatcher<'a> {
+    fn new(ifmatches: &'a Vec<IfnameMatch>) -> IfnameMatcher<'a> {
+        let links = if ifmatches.iter().any(|ifmatch| ifmatch.attr != IfAttr::Name) {
+            netlink::link_attrs()
+        } else {
+            Rc::new(HashMap::new())
+        };
+        IfnameMatcher {
+            ifmatches: ifmatches,
+            links: links,
+        }
+    }
+
+    // Returns what the match captured, if it matches the interface.
+    fn match_one(&self, ifmatch: &IfnameMatch, ifname: &str) -> Option<Vec<String>> {
+        let link = self.links.get(ifname);
+        let values: Vec<&str> = match ifmatch.attr {
+            IfAttr::Name => vec![ifname],
+            IfAttr::Altname => link
+                .map(|link| link.altnames.iter().map(|name| name.as_str()).collect())
+                .unwrap_or_default(),
+            _ => link
+                .and_then(|link| match ifmatch.attr {
+                    IfAttr::Driver => link.driver.as_ref(),
+                    IfAttr::Master => link.master.as_ref(),
+                    IfAttr::Kind => link.kind.as_ref(),
+                    _ => link.parentdev.as_ref(),
+                })
+                .map(|value| vec![value.as_str()])
+                .unwrap_or_default(),
+        };
+        values
+            .into_iter()
+            .find(|value| ifmatch.pat.matches(value))
+            .map(|value| ifmatch.pat.captures(value))
+    }
+
+    // Matches are evaluated in order, and the last one that matches decides.
+    fn find(&self, ifname: &str) -> Option<Vec<String>> {
+        let mut ret = None;
+        for ifmatch in self.ifmatches {
+            if let Some(captures) = self.match_one(ifmatch, ifname) {
+                ret = if ifmatch.exclude {
+                    None
+                } else {
+                    Some(captures)
+                };
+            }
+        }
+        ret
+    }
+
+    fn matches(&self, ifname: &str) -> bool {
+        self.find(ifname).is_some()
+    }
+
+    // The interface part of a key, including anything captured by the interface match.
+    fn key(&self, ifname: &str) -> Vec<(ct::KeyHead, String)> {
+        let mut key = vec![(ct::KeyHead::Ifname, ifname.to_string())];
+        if let Some(captures) = self.find(ifname) {
+            push_captures(&mut key, &captures);
+        }
+        key
+    }
+}
+
 fn is_unit(word: &String) -> bool {
     if let Some(first) = word.chars().nth(0) {
         first == '/'
@@ -80,11 +376,23 @@ fn is_unit(word: &String) -> bool {
     }
 }
 
-fn parse_unit_pfx<I>(it: &mut Peekable<I>) -> Result<ct::Unit, String>
+fn parse_pfx<I>(it: &mut Peekable<I>) -> ct::UPfx
 where
     I: Iterator<Item = char>,
 {
     let prefix = match it.peek() {
+        Some(&'E') => {
+            it.next();
+            ct::UPfx::Exa
+        }
+        Some(&'P') => {
+            it.next();
+            ct::UPfx::Peta
+        }
+        Some(&'T') => {
+            it.next();
+            ct::UPfx::Tera
+        }
         Some(&'G') => {
             it.next();
             ct::UPfx::Giga
@@ -112,12 +420,57 @@ where
         _ => ct::UPfx::None,
     };
 
+    // Binary prefixes are written with an 'i', e.g. Ki.
+    if it.peek() == Some(&'i') {
+        let binary = match prefix {
+            ct::UPfx::Kilo => ct::UPfx::Kibi,
+            ct::UPfx::Mega => ct::UPfx::Mebi,
+            ct::UPfx::Giga => ct::UPfx::Gibi,
+            ct::UPfx::Tera => ct::UPfx::Tebi,
+            _ => return prefix,
+        };
+        it.next();
+        return binary;
+    }
+    prefix
+}
+
+fn parse_unit_pfx<I>(it: &mut Peekable<I>) -> Result<ct::Unit, String>
+where
+    I: Iterator<Item = char> + Clone,
+{
+    let prefix = parse_pfx(it);
     let base = match it.next() {
-        Some('p') => ct::UBase::Packets,
+        Some('p') => {
+            let mut ahead = it.clone();
+            if ahead.next() == Some('p') && ahead.next() == Some('m') {
+                it.nth(1);
+                ct::UBase::Ppm
+            } else {
+                ct::UBase::Packets
+            }
+        }
         Some('s') => ct::UBase::Seconds,
         Some('B') => ct::UBase::Bytes,
         Some('b') => ct::UBase::Bits,
         Some('1') => ct::UBase::Units,
+        Some('x') => ct::UBase::Default,
+        Some('%') => {
+            if it.peek() == Some(&'t') {
+                if it.by_ref().take(4).collect::<String>() != "time" {
+                    return Err("Unknown unit, did you mean %time?".to_string());
+                }
+                ct::UBase::PercentTime
+            } else {
+                ct::UBase::Percent
+            }
+        }
+        Some('q') => {
+            if it.by_ref().take(5).collect::<String>() != "uanta" {
+                return Err("Unknown unit, did you mean quanta?".to_string());
+            }
+            ct::UBase::Quanta
+        }
         Some(c) => {
             return Err(format!("Unknown unit, '{}'", c));
         }
@@ -159,13 +512,89 @@ fn parse_unit_freq(str: &str) -> Result<(ct::Unit, ct::UFreq), String> {
     return Err(format!("Unit suffix not understood: {}", rest));
 }
 
+// A number with an optional prefix, e.g. 1.5M, scaled to the unprefixed value.
+fn parse_number_pfx(str: &str) -> Option<ct::Value> {
+    let digits = str
+        .find(|ch: char| !ch.is_digit(10) && ch != '.')
+        .unwrap_or(str.len());
+    let mut it = str[digits..].chars().peekable();
+    let prefix = parse_pfx(&mut it);
+    if it.next().is_some() {
+        return None;
+    }
+    str[..digits]
+        .parse::<f64>()
+        .ok()
+        .and_then(ct::Value::checked_from_num)
+        .map(|number| number.saturating_mul(prefix.scale()))
+}
+
 fn parse_unit_chain(str: &str) -> Result<ct::UnitChain, String> {
     let mut units = Vec::<ct::Unit>::new();
     let mut freq = ct::UFreq::AsIs;
+    let mut fixed_prefix = false;
+    let mut speed = None;
+    let mut limit = None;
 
     // The unit string starts with a '/', so skip the first (empty) element.
-    for substr in str.split('/').skip(1) {
+    let substrs: Vec<&str> = str.split('/').skip(1).collect();
+    for (i, substr) in substrs.iter().enumerate() {
+        // The last unit can have its prefix fixed, e.g. /B/=MiB.
+        let mut substr = *substr;
+        if substr.starts_with('=') {
+            if i == 0 {
+                return Err(format!(
+                    "A fixed prefix needs a unit to convert from, e.g. /B/{}",
+                    substr
+                ));
+            }
+            if i + 1 != substrs.len() {
+                return Err(format!(
+                    "Only the last unit can have a fixed prefix: {}",
+                    str
+                ));
+            }
+            fixed_prefix = true;
+            substr = &substr[1..];
+        }
+
+        // Any unit can carry the link speed, e.g. /quanta/us@100G.
+        if let Some(pos) = substr.find('@') {
+            let bps = parse_number_pfx(&substr[pos + 1..])
+                .filter(|bps| *bps > 0)
+                .ok_or(format!("Invalid link speed: {}", &substr[pos + 1..]))?;
+            if speed.replace(bps).is_some() {
+                return Err(format!("Only one link speed allowed in {}", str));
+            }
+            substr = &substr[..pos];
+        }
+
+        // A ratio can be of a limit given in the previous unit, e.g. /B/%of1M.
+        let mut has_limit = false;
+        if let Some(pos) = substr.find("of") {
+            if i == 0 {
+                return Err(format!(
+                    "A limit needs a unit to convert from, e.g. /B/{}",
+                    substr
+                ));
+            }
+            let number = parse_number_pfx(&substr[pos + 2..])
+                .filter(|number| *number > 0)
+                .ok_or(format!("Invalid limit: {}", &substr[pos + 2..]))?;
+            if limit.replace(number).is_some() {
+                return Err(format!("Only one limit allowed in {}", str));
+            }
+            has_limit = true;
+            substr = &substr[..pos];
+        }
+
         let (unit, this_freq) = parse_unit_freq(substr)?;
+        if has_limit && !unit.base.is_ratio() {
+            return Err(format!(
+                "Only % and ppm can be of a limit, not {}",
+                unit.base.to_string()
+            ));
+        }
         if this_freq != ct::UFreq::AsIs {
             if freq != ct::UFreq::AsIs {
                 return Err("Only one frequency allowed in a unit chain.".to_string());
@@ -175,9 +604,48 @@ fn parse_unit_chain(str: &str) -> Result<ct::UnitChain, String> {
         units.push(unit);
     }
 
+    if let Some(unit) = units.iter().find(|unit| unit.base == ct::UBase::Default) {
+        if units.len() > 1 || unit.prefix != ct::UPfx::None {
+            return Err(format!(
+                "The default unit stands alone, as /x, /dx or /xps: {}",
+                str
+            ));
+        }
+    }
+
+    for pair in units.windows(2) {
+        let (prev, unit) = (&pair[0], &pair[1]);
+        match ct::conversion(prev.base, unit.base, limit) {
+            Some(ct::Conversion::QuantaTime) if speed.is_none() => {
+                return Err(format!(
+                    "Converting quanta to time needs a link speed, e.g. {}@100G",
+                    str
+                ));
+            }
+            Some(ct::Conversion::TimeShare(_)) if freq != ct::UFreq::PerSecond => {
+                return Err(format!(
+                    "A share of time needs time per second, e.g. /usps/%: {}",
+                    str
+                ));
+            }
+            Some(_) => {}
+            None => {
+                return Err(format!(
+                    "Can't convert {} to {} in {}",
+                    prev.base.to_string(),
+                    unit.base.to_string(),
+                    str
+                ));
+            }
+        }
+    }
+
     Ok(ct::UnitChain {
         units: units,
         freq: freq,
+        fixed_prefix: fixed_prefix,
+        speed: speed,
+        limit: limit,
     })
 }
 
@@ -273,97 +741,445 @@ fn parse_hnmatch(words: &mut Peekable<std::slice::Iter<String>>) -> Option<Qdisc
     }
 }
 
-fn parse_value_filter_one(word: &String) -> Option<Box<dyn ct::CounterValueFilter>> {
+#[derive(Debug)]
+enum CpuPattern {
+    Range(u32, u32),
+    Name(NamePattern),
+}
+
+// A CPU match. CPUs are selected by numbers and ranges, e.g. 0-3,8, or by a ~regex on the CPU
+// number, whose groups are captured. Exclusions, written as !0-3 or !~regex, remove CPUs that the
+// matches before them let through.
+#[derive(Debug)]
+struct CpuMatch {
+    pat: CpuPattern,
+    exclude: bool,
+}
+
+impl CpuMatch {
+    fn any() -> CpuMatch {
+        CpuMatch {
+            pat: CpuPattern::Range(0, std::u32::MAX),
+            exclude: false,
+        }
+    }
+
+    // Returns what the match captured, if it matches the CPU.
+    fn match_one(&self, cpu: u32) -> Option<Vec<String>> {
+        match &self.pat {
+            CpuPattern::Range(first, last) if *first <= cpu && cpu <= *last => Some(Vec::new()),
+            CpuPattern::Range(_, _) => None,
+            CpuPattern::Name(pat) => {
+                let name = cpu.to_string();
+                if pat.matches(&name) {
+                    Some(pat.captures(&name))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+// Matches are evaluated in order, and the last one that matches decides.
+fn find_cpumatch(cpumatches: &Vec<CpuMatch>, cpu: u32) -> Option<Vec<String>> {
+    let mut ret = None;
+    for cpumatch in cpumatches {
+        if let Some(captures) = cpumatch.match_one(cpu) {
+            ret = if cpumatch.exclude {
+                None
+            } else {
+                Some(captures)
+            };
+        }
+    }
+    ret
+}
+
+// The CPU part of a key, including anything captured by the CPU match.
+fn cpu_key(cpu: u32, captures: &Vec<String>) -> Vec<(ct::KeyHead, String)> {
+    let mut key = vec![(ct::KeyHead::Cpu, cpu.to_string())];
+    push_captures(&mut key, captures);
+    key
+}
+
+// Numbers and ranges, possibly excluded, are always taken as CPU matches. A ~regex is only taken
+// right after the cpu keyword, elsewhere it is where the counter matches start.
+fn is_cpumatch(word: &String) -> bool {
+    let word = word.strip_prefix('!').unwrap_or(word);
+    word.starts_with(|ch: char| ch.is_ascii_digit())
+        && word
+            .chars()
+            .all(|ch| ch.is_ascii_digit() || ch == '-' || ch == ',')
+}
+
+// A CPU match is a comma-separated list of CPU numbers and ranges, e.g. 0-3,8, or a ~regex.
+fn parse_cpumatch_one(word: &String) -> Result<Vec<CpuMatch>, String> {
+    let exclude = word.starts_with('!');
+    let spec = if exclude { &word[1..] } else { &word[..] };
+    if spec.starts_with('~') {
+        return Ok(vec![CpuMatch {
+            pat: CpuPattern::Name(NamePattern::new(spec)?),
+            exclude: exclude,
+        }]);
+    }
+    if !is_cpumatch(word) {
+        return Err(format!(
+            "Expected CPU numbers, ranges or a ~regex after cpu: {}",
+            word
+        ));
+    }
+
+    let mut ret = Vec::new();
+    for part in spec.split(',') {
+        let bounds: Vec<&str> = part.splitn(2, '-').collect();
+        let parse_cpu = |str: &str| {
+            str.parse::<u32>()
+                .map_err(|_| format!("Invalid CPU number in {}", word))
+        };
+        let (first, last) = if bounds.len() == 2 {
+            (parse_cpu(bounds[0])?, parse_cpu(bounds[1])?)
+        } else {
+            let cpu = parse_cpu(part)?;
+            (cpu, cpu)
+        };
+        if first > last {
+            return Err(format!("Invalid CPU range: {}", part));
+        }
+        ret.push(CpuMatch {
+            pat: CpuPattern::Range(first, last),
+            exclude: exclude,
+        });
+    }
+    Ok(ret)
+}
+
+// Syntax: [cpu cpumatch [cpumatch ...] [cpu cpumatch ...]]
+fn parse_cpumatches(
+    words: &mut Peekable<std::slice::Iter<String>>,
+) -> Result<Vec<CpuMatch>, String> {
+    let mut cpumatches = Vec::new();
+    while words.peek().map(|word| *word == "cpu") == Some(true) {
+        words.next();
+        match words.peek() {
+            Some(word) => cpumatches.append(&mut parse_cpumatch_one(word)?),
+            None => return Err("Expected one or more CPU matches after cpu".to_string()),
+        }
+        words.next();
+        while let Some(word) = words.peek() {
+            if !is_cpumatch(word) {
+                break;
+            }
+            cpumatches.append(&mut parse_cpumatch_one(word)?);
+            words.next();
+        }
+    }
+    // No CPU matches select all CPUs, and a list that starts with an exclusion excludes from all
+    // of them.
+    if cpumatches.first().map_or(true, |cpumatch| cpumatch.exclude) {
+        cpumatches.insert(0, CpuMatch::any());
+    }
+
+    Ok(cpumatches)
+}
+
+// Devlink instances are given as bus/device, e.g. pci/0000:03:00.0.
+fn is_devmatch(word: &String) -> bool {
+    word.contains('/') && !is_unit(word) && !is_ns(word)
+}
+
+// Syntax: [devmatch [devmatch ...]]
+fn parse_devmatches(
+    words: &mut Peekable<std::slice::Iter<String>>,
+) -> Result<Vec<NamePattern>, String> {
+    let mut devmatches = Vec::new();
+    while let Some(word) = words.peek() {
+        if !is_devmatch(word) {
+            break;
+        }
+        devmatches.push(NamePattern::new(word)?);
+        words.next();
+    }
+    if devmatches.is_empty() {
+        devmatches.push(NamePattern::any());
+    }
+    Ok(devmatches)
+}
+
+// Devlink resource paths, e.g. /kvd/linear. These look like units, but they precede the counter
+// matches, where a unit is not allowed. A path component is required, so that e.g. /% is still
+// taken as a unit.
+fn is_resmatch(word: &String) -> bool {
+    let path = word.strip_prefix('~').unwrap_or(word);
+    path.starts_with('/')
+        && path[1..].chars().next().map_or(false, |c| {
+            c.is_alphanumeric() || c == '_' || c == '*' || c == '.'
+        })
+}
+
+// Syntax: [resmatch [resmatch ...]]
+fn parse_resmatches(
+    words: &mut Peekable<std::slice::Iter<String>>,
+) -> Result<Vec<NamePattern>, String> {
+    let mut resmatches = Vec::new();
+    while let Some(word) = words.peek() {
+        if !is_resmatch(word) {
+            break;
+        }
+        resmatches.push(NamePattern::new(word)?);
+        words.next();
+    }
+    if resmatches.is_empty() {
+        resmatches.push(NamePattern::any());
+    }
+    Ok(resmatches)
+}
+
+const DEFAULT_SPIKE_FACTOR: f64 = 3.0;
+
+static THRESHOLD_OPS: [(&str, ct::ThresholdOp); 5] = [
+    (">=", ct::ThresholdOp::Ge),
+    ("<=", ct::ThresholdOp::Le),
+    (">", ct::ThresholdOp::Gt),
+    ("<", ct::ThresholdOp::Lt),
+    ("=", ct::ThresholdOp::Eq),
+];
+
+// Syntax: [avg]op number[prefix][unit], e.g. >1K, avg<=10M, >1G/bps
+fn parse_threshold(word: &String) -> Result<Option<ct::ThresholdCounterFilter>, String> {
+    let avg = word.starts_with("avg");
+    let rest = if avg { &word[3..] } else { &word[..] };
+    let (op, rest) = match THRESHOLD_OPS.iter().find(|(op, _)| rest.starts_with(op)) {
+        Some((op_str, op)) => (op.clone(), &rest[op_str.len()..]),
+        None => return Ok(None),
+    };
+
+    let (number, unit) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], Some(parse_unit_chain(&rest[pos..])?)),
+        None => (rest, None),
+    };
+    let threshold = parse_number_pfx(number).ok_or(format!("Invalid threshold: {}", word))?;
+
+    // The threshold is compared in the units that the chain ends in. The default unit placeholder
+    // compares in whatever unit the counter is shown in.
+    let (threshold, base) = match unit.as_ref().and_then(|unit| unit.units.last()) {
+        Some(unit) if unit.base == ct::UBase::Default => (threshold, None),
+        Some(unit) => (
+            threshold.saturating_mul(unit.prefix.scale()),
+            Some(unit.base),
+        ),
+        None => (threshold, None),
+    };
+
+    // The threshold's frequency is not converted: >1G/bps only applies to counters shown per
+    // second, >1G/dB only to deltas.
+    let freq = unit
+        .map(|unit| unit.freq)
+        .filter(|freq| *freq != ct::UFreq::AsIs);
+
+    Ok(Some(ct::ThresholdCounterFilter {
+        avg: avg,
+        op: op,
+        threshold: threshold,
+        base: base,
+        freq: freq,
+    }))
+}
+
+// Syntax: spike[>factor]
+fn parse_spike(word: &String) -> Result<Option<ct::SpikeCounterFilter>, String> {
+    let factor = if word == "spike" {
+        DEFAULT_SPIKE_FACTOR
+    } else if word.starts_with("spike>") {
+        word[6..]
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid spike factor: {}", word))?
+    } else {
+        return Ok(None);
+    };
+    match ct::Value::checked_from_num(factor) {
+        Some(factor) if factor > 0 => Ok(Some(ct::SpikeCounterFilter { factor: factor })),
+        _ => Err(format!("Invalid spike factor: {}", word)),
+    }
+}
+
+fn parse_value_filter_one(
+    word: &String,
+) -> Result<Option<Box<dyn ct::CounterValueFilter>>, String> {
     if word == "non0" {
-        Some(Box::new(ct::NonZeroCounterFilter {}))
+        Ok(Some(Box::new(ct::NonZeroCounterFilter {})))
+    } else if word == "changed" {
+        Ok(Some(Box::new(ct::ChangedCounterFilter {})))
+    } else if word == "stuck" {
+        Ok(Some(Box::new(ct::StuckCounterFilter {})))
+    } else if let Some(spike) = parse_spike(word)? {
+        Ok(Some(Box::new(spike)))
+    } else if let Some(threshold) = parse_threshold(word)? {
+        Ok(Some(Box::new(threshold)))
     } else {
-        None
+        Ok(None)
     }
 }
 
 fn peek_value_filter(
     words: &mut Peekable<std::slice::Iter<String>>,
-) -> Option<Box<dyn ct::CounterValueFilter>> {
+) -> Result<Option<Box<dyn ct::CounterValueFilter>>, String> {
     if let Some(word) = words.peek() {
         parse_value_filter_one(word)
     } else {
-        None
+        Ok(None)
     }
 }
 
 fn parse_value_filter(
     words: &mut Peekable<std::slice::Iter<String>>,
-) -> Option<Box<dyn ct::CounterValueFilter>> {
-    if let Some(vfilt) = peek_value_filter(words) {
+) -> Result<Option<Box<dyn ct::CounterValueFilter>>, String> {
+    if let Some(vfilt) = peek_value_filter(words)? {
         words.next();
-        Some(vfilt)
+        Ok(Some(vfilt))
     } else {
-        None
+        Ok(None)
     }
 }
 
 #[derive(Debug)]
 struct CounterNameMatch {
-    pat: glob::Pattern,
+    pat: NamePattern,
+    exclude: bool,
     unit: Option<ct::UnitChain>,
     vfilt: Vec<Box<dyn ct::CounterValueFilter>>,
 }
 
+impl CounterNameMatch {
+    fn counter_imm(
+        &self,
+        key: ct::CounterKey,
+        value: u64,
+        default_unit: &ct::UnitChain,
+    ) -> ct::CounterImm {
+        ct::CounterImm {
+            key: key,
+            value: value,
+            unit: match &self.unit {
+                Some(unit) => unit.resolve(default_unit),
+                None => default_unit.clone(),
+            },
+            filter: self.vfilt.iter().map(|vf| vf.clone_box()).collect(),
+        }
+    }
+}
+
+// A counter name match, with anything its pattern captured from the name.
+struct CounterMatch<'a> {
+    ctmatch: &'a CounterNameMatch,
+    captures: Vec<String>,
+}
+
+impl<'a> CounterMatch<'a> {
+    fn counter_imm(
+        &self,
+        mut key: ct::CounterKey,
+        value: u64,
+        default_unit: &ct::UnitChain,
+    ) -> ct::CounterImm {
+        push_captures(&mut key.key, &self.captures);
+        self.ctmatch.counter_imm(key, value, default_unit)
+    }
+}
+
+// Names that counter matches give literally, i.e. that are not globs or regexes.
+fn plain_names(ctmatches: &Vec<CounterNameMatch>) -> Vec<String> {
+    ctmatches
+        .iter()
+        .filter_map(|ctmatch| match &ctmatch.pat {
+            NamePattern::Glob(pat) if !pat.as_str().contains(|ch| "*?[".contains(ch)) => {
+                Some(pat.as_str().to_string())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn find_ctmatch<'a>(ctmatches: &'a Vec<CounterNameMatch>, name: &str) -> Option<CounterMatch<'a>> {
+    // The first inclusion that matches gives the unit and filters, unless an exclusion after it
+    // matches as well. An inclusion after that can bring the counter back.
+    let mut ret = None;
+    for ctmatch in ctmatches {
+        if !ctmatch.pat.matches(name) {
+            continue;
+        }
+        if ctmatch.exclude {
+            ret = None;
+        } else if ret.is_none() {
+            ret = Some(ctmatch);
+        }
+    }
+    ret.map(|ctmatch| CounterMatch {
+        ctmatch: ctmatch,
+        captures: ctmatch.pat.captures(name),
+    })
+}
+
 #[derive(Debug)]
 struct EthtoolCounterRule {
-    ifmatches: Vec<glob::Pattern>,
+    ifmatches: Vec<IfnameMatch>,
     ctmatches: Vec<CounterNameMatch>,
 }
 
 impl ct::CounterRule for EthtoolCounterRule {
     fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
         let mut ret = Vec::new();
+        let ifmatcher = IfnameMatcher::new(&self.ifmatches);
         for ifname in netlink::ifnames()
             .iter()
-            .filter(|ifname| self.ifmatches.iter().any(|ref pat| pat.matches(&ifname)))
+            .filter(|ifname| ifmatcher.matches(&ifname))
         {
             for stat in ethtool_ss::stats_for(&ifname) {
-                for ctmatch in &self.ctmatches {
-                    if ctmatch.pat.matches(&stat.name) {
-                        let unit = if ctmatch.unit.is_some() {
-                            ctmatch.unit.as_ref().unwrap().clone()
-                        } else {
-                            ct::unit_units_ps()
-                        };
-                        ret.push(ct::CounterImm {
-                            key: ct::CounterKey {
-                                ctns: "ethtool",
-                                key: vec![
-                                    (ct::KeyHead::Ifname, ifname.clone()),
-                                    (ct::KeyHead::Name, stat.name.clone()),
-                                ],
-                            },
-                            value: stat.value,
-                            unit: unit,
-                            filter: ctmatch.vfilt.iter().map(|vf| vf.clone_box()).collect(),
-                        });
-                        break;
-                    }
+                if let Some(ctmatch) = find_ctmatch(&self.ctmatches, &stat.name) {
+                    let mut key = ifmatcher.key(&ifname);
+                    key.push((ct::KeyHead::Name, stat.name.clone()));
+                    ret.push(ctmatch.counter_imm(
+                        ct::CounterKey {
+                            ctns: "ethtool",
+                            key: key,
+                        },
+                        stat.value,
+                        &ct::unit_units_ps(),
+                    ));
                 }
             }
         }
         Ok(ret)
     }
+
+    fn names(&self) -> Vec<String> {
+        let ifmatcher = IfnameMatcher::new(&self.ifmatches);
+        netlink::ifnames()
+            .iter()
+            .filter(|ifname| ifmatcher.matches(&ifname))
+            .flat_map(|ifname| ethtool_ss::stats_for(&ifname))
+            .map(|stat| stat.name)
+            .collect()
+    }
+    fn plain_names(&self) -> Vec<String> {
+        plain_names(&self.ctmatches)
+    }
 }
 
 fn parse_ifmatches(
     words: &mut Peekable<std::slice::Iter<String>>,
-) -> Result<Vec<glob::Pattern>, String> {
+) -> Result<Vec<IfnameMatch>, String> {
     let mut ifmatches = Vec::new();
-    while let Some(pat) = match parse_ifmatch(words) {
-        Ok(maybe_pat) => maybe_pat,
-        Err(err) => return Err(err.msg.to_string()),
-    } {
+    while let Some(pat) = parse_ifmatch(words)? {
         ifmatches.push(pat);
     }
     if ifmatches.is_empty() {
         return Err("Expected one or more @ifmatches".to_string());
     }
+    // A list that starts with an exclusion excludes from all interfaces.
+    if ifmatches[0].exclude {
+        ifmatches.insert(0, any_ifname());
+    }
 
     Ok(ifmatches)
 }
@@ -394,28 +1210,37 @@ fn parse_ctmatches(
             return Err(format!("Unexpected unit before counter: {}", word));
         }
 
-        let mut ctmatch;
-        match glob::Pattern::new(&word) {
-            Ok(pat) => {
-                ctmatch = CounterNameMatch {
-                    pat: pat,
-                    unit: None,
-                    vfilt: Vec::new(),
-                };
-            }
-            Err(err) => return Err(err.msg.to_string()),
-        }
+        // Counters are excluded with !glob.
+        let exclude = word.starts_with('!');
+        let mut ctmatch = CounterNameMatch {
+            pat: NamePattern::new(if exclude { &word[1..] } else { &word })?,
+            exclude: exclude,
+            unit: None,
+            vfilt: Vec::new(),
+        };
         words.next();
 
-        while let Some(vf) = parse_value_filter(words) {
-            ctmatch.vfilt.push(vf);
+        if exclude {
+            if peek_value_filter(words)?.is_some() {
+                return Err(format!(
+                    "Value filters don't apply to an exclusion: {}",
+                    words.peek().unwrap()
+                ));
+            }
+        } else {
+            while let Some(vf) = parse_value_filter(words)? {
+                ctmatch.vfilt.push(vf);
+            }
         }
 
         ctmatches.push(ctmatch);
 
+        // A unit after an exclusion still applies to the inclusions before it.
         if let Some(u) = parse_unit(words)? {
             for ctmatch in ctmatches.iter_mut().rev() {
-                if ctmatch.unit.is_none() {
+                if ctmatch.exclude {
+                    continue;
+                } else if ctmatch.unit.is_none() {
                     ctmatch.unit = Some(u.clone());
                 } else {
                     break;
@@ -424,12 +1249,17 @@ fn parse_ctmatches(
         }
     }
 
-    if ctmatches.is_empty() {
-        ctmatches.push(CounterNameMatch {
-            pat: glob::Pattern::new("*").unwrap(),
-            unit: None,
-            vfilt: Vec::new(),
-        });
+    // Exclusions alone exclude from all counters.
+    if ctmatches.iter().all(|ctmatch| ctmatch.exclude) {
+        ctmatches.insert(
+            0,
+            CounterNameMatch {
+                pat: NamePattern::any(),
+                exclude: false,
+                unit: None,
+                vfilt: Vec::new(),
+            },
+        );
     }
 
     Ok(ctmatches)
@@ -456,50 +1286,100 @@ impl Parser for EthtoolParser {
     }
 }
 
+#[derive(Debug)]
+struct XdpCounterRule {
+    ifmatches: Vec<IfnameMatch>,
+    ctmatches: Vec<CounterNameMatch>,
+}
+
+impl ct::CounterRule for XdpCounterRule {
+    fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
+        let mut ret = Vec::new();
+        let ifmatcher = IfnameMatcher::new(&self.ifmatches);
+        for ifname in netlink::ifnames()
+            .iter()
+            .filter(|ifname| ifmatcher.matches(&ifname))
+        {
+            for stat in xdp::stats_for(&ifname) {
+                if let Some(ctmatch) = find_ctmatch(&self.ctmatches, &stat.name) {
+                    let mut key = ifmatcher.key(&ifname);
+                    key.push((ct::KeyHead::Name, stat.name.clone()));
+                    ret.push(ctmatch.counter_imm(
+                        ct::CounterKey {
+                            ctns: "xdp",
+                            key: key,
+                        },
+                        stat.value,
+                        &ct::unit_packets_ps(),
+                    ));
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
+
+struct XdpParser {}
+
+impl Parser for XdpParser {
+    // Syntax: @ifmatch* [@if2match* ...] ctmatch* [ctmatch* ...]
+    fn parse(
+        &self,
+        words: &mut Peekable<std::slice::Iter<String>>,
+    ) -> Result<Vec<Box<dyn ct::CounterRule>>, String> {
+        if words.peek().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let ret: Vec<Box<dyn ct::CounterRule>> = vec![Box::new(XdpCounterRule {
+            ifmatches: parse_ifmatches(words)?,
+            ctmatches: parse_ctmatches(words)?,
+        })];
+
+        Ok(ret)
+    }
+}
+
 #[derive(Debug)]
 struct LinkCounterRule {
-    ifmatches: Vec<glob::Pattern>,
+    ifmatches: Vec<IfnameMatch>,
     ctmatches: Vec<CounterNameMatch>,
 }
 
 impl ct::CounterRule for LinkCounterRule {
     fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
         let mut ret = Vec::new();
+        let ifmatcher = IfnameMatcher::new(&self.ifmatches);
         for link_stat in netlink::get_link_stats() {
-            if !self
-                .ifmatches
-                .iter()
-                .any(|ref pat| pat.matches(&link_stat.ifname))
-            {
+            if !ifmatcher.matches(&link_stat.ifname) {
                 continue;
             }
 
-            for ctmatch in &self.ctmatches {
-                if ctmatch.pat.matches(&link_stat.name) {
-                    let unit = if ctmatch.unit.is_some() {
-                        ctmatch.unit.as_ref().unwrap().clone()
-                    } else {
-                        link_stat.default_unit.clone()
-                    };
-
-                    ret.push(ct::CounterImm {
-                        key: ct::CounterKey {
-                            ctns: "link",
-                            key: vec![
-                                (ct::KeyHead::Ifname, link_stat.ifname.clone()),
-                                (ct::KeyHead::Name, link_stat.name),
-                            ],
-                        },
-                        value: link_stat.value,
-                        unit: unit,
-                        filter: ctmatch.vfilt.iter().map(|vf| vf.clone_box()).collect(),
-                    });
-                    break;
-                }
+            if let Some(ctmatch) = find_ctmatch(&self.ctmatches, &link_stat.name) {
+                let mut key = ifmatcher.key(&link_stat.ifname);
+                key.push((ct::KeyHead::Name, link_stat.name));
+                ret.push(ctmatch.counter_imm(
+                    ct::CounterKey {
+                        ctns: "link",
+                        key: key,
+                    },
+                    link_stat.value,
+                    &link_stat.default_unit,
+                ));
             }
         }
         Ok(ret)
     }
+
+    fn names(&self) -> Vec<String> {
+        netlink::get_link_stats()
+            .drain(..)
+            .map(|link_stat| link_stat.name)
+            .collect()
+    }
+    fn plain_names(&self) -> Vec<String> {
+        plain_names(&self.ctmatches)
+    }
 }
 
 struct LinkParser {}
@@ -526,7 +1406,7 @@ impl Parser for LinkParser {
 
 #[derive(Debug)]
 struct QdiscCounterRule {
-    ifmatches: Vec<glob::Pattern>,
+    ifmatches: Vec<IfnameMatch>,
     hnmatches: Vec<QdiscHandleMatch>,
     ctmatches: Vec<CounterNameMatch>,
 }
@@ -534,12 +1414,9 @@ struct QdiscCounterRule {
 impl ct::CounterRule for QdiscCounterRule {
     fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
         let mut ret = Vec::new();
-        for qdisc_stat in netlink::qdiscs() {
-            if !self
-                .ifmatches
-                .iter()
-                .any(|ref pat| pat.matches(&qdisc_stat.ifname))
-            {
+        let ifmatcher = IfnameMatcher::new(&self.ifmatches);
+        for qdisc_stat in netlink::qdiscs()? {
+            if !ifmatcher.matches(&qdisc_stat.ifname) {
                 continue;
             }
 
@@ -581,36 +1458,30 @@ impl ct::CounterRule for QdiscCounterRule {
                 continue;
             }
 
-            for ctmatch in &self.ctmatches {
-                if ctmatch.pat.matches(&qdisc_stat.name) {
-                    let parent = if pnmajor == 0xffff && pnminor == 0xffff {
-                        "root".to_string()
-                    } else {
-                        format!("{:x}:{:x}", pnmajor, pnminor)
-                    };
-                    let unit = if ctmatch.unit.is_some() {
-                        ctmatch.unit.as_ref().unwrap().clone()
-                    } else {
-                        qdisc_stat.default_unit.clone()
-                    };
-
-                    ret.push(ct::CounterImm {
-                        key: ct::CounterKey {
-                            ctns: "qdisc",
-                            key: vec![
-                                (ct::KeyHead::Ifname, qdisc_stat.ifname.clone()),
-                                (ct::KeyHead::Parent, parent),
-                                (ct::KeyHead::Handle, format!("{:x}:", hnmajor)),
-                                (ct::KeyHead::Kind, qdisc_stat.kind),
-                                (ct::KeyHead::Name, qdisc_stat.name),
-                            ],
-                        },
-                        value: qdisc_stat.value,
-                        unit: unit,
-                        filter: ctmatch.vfilt.iter().map(|vf| vf.clone_box()).collect(),
-                    });
-                    break;
+            if let Some(ctmatch) = find_ctmatch(&self.ctmatches, &qdisc_stat.name) {
+                let parent = if pnmajor == 0xffff && pnminor == 0xffff {
+                    "root".to_string()
+                } else {
+                    format!("{:x}:{:x}", pnmajor, pnminor)
+                };
+
+                let mut key = ifmatcher.key(&qdisc_stat.ifname);
+                key.push((ct::KeyHead::Parent, parent));
+                key.push((ct::KeyHead::Handle, format!("{:x}:", hnmajor)));
+                key.push((ct::KeyHead::Kind, qdisc_stat.kind));
+                if let Some(vq) = qdisc_stat.vq {
+                    key.push((ct::KeyHead::Vq, vq.to_string()));
                 }
+                key.push((ct::KeyHead::Name, qdisc_stat.name));
+
+                ret.push(ctmatch.counter_imm(
+                    ct::CounterKey {
+                        ctns: "qdisc",
+                        key: key,
+                    },
+                    qdisc_stat.value,
+                    &qdisc_stat.default_unit,
+                ));
             }
         }
         Ok(ret)
@@ -641,28 +1512,1330 @@ impl Parser for QdiscParser {
     }
 }
 
-const PARSERS: [(&str, &dyn Parser); 3] = [
-    ("ethtool", &EthtoolParser {}),
-    ("link", &LinkParser {}),
-    ("qdisc", &QdiscParser {}),
-];
+#[derive(Debug)]
+struct SnmpCounterRule {
+    ctmatches: Vec<CounterNameMatch>,
+}
 
-pub fn parse_expr(
-    words: &mut Peekable<std::slice::Iter<String>>,
-) -> Result<Vec<Box<dyn ct::CounterRule>>, String> {
-    let mut ret = Vec::new();
-    loop {
-        let ns = parse_ns_opt(words).unwrap_or("ethtool".to_string());
-        let mut nv = PARSERS
-            .iter()
-            .find(|(name, _)| *name == ns)
-            .ok_or(format!("Unknown namespace: {}", ns))?
-            .1
-            .parse(words)?;
-        if nv.is_empty() {
-            break;
+impl ct::CounterRule for SnmpCounterRule {
+    fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
+        let mut ret = Vec::new();
+        for snmp_stat in snmp::stats()? {
+            // Counters are matched as Group.Name, e.g. Tcp.RetransSegs.
+            let full_name = format!("{}.{}", snmp_stat.group, snmp_stat.name);
+            if let Some(ctmatch) = find_ctmatch(&self.ctmatches, &full_name) {
+                ret.push(ctmatch.counter_imm(
+                    ct::CounterKey {
+                        ctns: "snmp",
+                        key: vec![
+                            (ct::KeyHead::Proto, snmp_stat.group),
+                            (ct::KeyHead::Name, snmp_stat.name),
+                        ],
+                    },
+                    snmp_stat.value,
+                    &snmp_stat.default_unit,
+                ));
+            }
         }
-        ret.append(&mut nv);
+        Ok(ret)
+    }
+
+    fn names(&self) -> Vec<String> {
+        snmp::stats()
+            .unwrap_or_default()
+            .drain(..)
+            .map(|snmp_stat| format!("{}.{}", snmp_stat.group, snmp_stat.name))
+            .collect()
+    }
+    fn plain_names(&self) -> Vec<String> {
+        plain_names(&self.ctmatches)
+    }
+}
+
+struct SnmpParser {}
+
+impl Parser for SnmpParser {
+    // Syntax: ctmatch* [ctmatch* ...]
+    fn parse(
+        &self,
+        words: &mut Peekable<std::slice::Iter<String>>,
+    ) -> Result<Vec<Box<dyn ct::CounterRule>>, String> {
+        if words.peek().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let ret: Vec<Box<dyn ct::CounterRule>> = vec![Box::new(SnmpCounterRule {
+            ctmatches: parse_ctmatches(words)?,
+        })];
+        Ok(ret)
+    }
+}
+
+#[derive(Debug)]
+struct SoftnetCounterRule {
+    cpumatches: Vec<CpuMatch>,
+    ctmatches: Vec<CounterNameMatch>,
+}
+
+impl ct::CounterRule for SoftnetCounterRule {
+    fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
+        let mut ret = Vec::new();
+        for softnet_stat in softnet::stats()? {
+            let captures = match find_cpumatch(&self.cpumatches, softnet_stat.cpu) {
+                Some(captures) => captures,
+                None => continue,
+            };
+
+            if let Some(ctmatch) = find_ctmatch(&self.ctmatches, &softnet_stat.name) {
+                let mut key = cpu_key(softnet_stat.cpu, &captures);
+                key.push((ct::KeyHead::Name, softnet_stat.name));
+                ret.push(ctmatch.counter_imm(
+                    ct::CounterKey {
+                        ctns: "softnet",
+                        key: key,
+                    },
+                    softnet_stat.value,
+                    &softnet_stat.default_unit,
+                ));
+            }
+        }
+        Ok(ret)
+    }
+}
+
+struct SoftnetParser {}
+
+impl Parser for SoftnetParser {
+    // Syntax: [cpu cpumatch [cpumatch ...] ...] ctmatch* [ctmatch* ...]
+    fn parse(
+        &self,
+        words: &mut Peekable<std::slice::Iter<String>>,
+    ) -> Result<Vec<Box<dyn ct::CounterRule>>, String> {
+        if words.peek().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let cpumatches = parse_cpumatches(words)?;
+        let ctmatches = parse_ctmatches(words)?;
+
+        let ret: Vec<Box<dyn ct::CounterRule>> = vec![Box::new(SoftnetCounterRule {
+            cpumatches: cpumatches,
+            ctmatches: ctmatches,
+        })];
+        Ok(ret)
+    }
+}
+
+#[derive(Debug)]
+struct IrqCounterRule {
+    cpumatches: Vec<CpuMatch>,
+    total: bool,
+    ctmatches: Vec<CounterNameMatch>,
+}
+
+impl ct::CounterRule for IrqCounterRule {
+    fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
+        let mut ret = Vec::new();
+        for irq_stat in interrupts::stats()? {
+            let ctmatch = match find_ctmatch(&self.ctmatches, &irq_stat.name) {
+                Some(ctmatch) => ctmatch,
+                None => continue,
+            };
+
+            // Captures differ from CPU to CPU, so the sums don't get them.
+            let counts = irq_stat.counts.iter().filter_map(|(cpu, count)| {
+                find_cpumatch(&self.cpumatches, *cpu).map(|captures| (*cpu, *count, captures))
+            });
+            if self.total {
+                ret.push(ctmatch.counter_imm(
+                    ct::CounterKey {
+                        ctns: "irq",
+                        key: vec![
+                            (ct::KeyHead::Irq, irq_stat.irq.clone()),
+                            (ct::KeyHead::Name, irq_stat.name.clone()),
+                        ],
+                    },
+                    counts.map(|(_, count, _)| count).sum(),
+                    &ct::unit_units_ps(),
+                ));
+            } else {
+                for (cpu, count, captures) in counts {
+                    let mut key = vec![(ct::KeyHead::Irq, irq_stat.irq.clone())];
+                    key.append(&mut cpu_key(cpu, &captures));
+                    key.push((ct::KeyHead::Name, irq_stat.name.clone()));
+                    ret.push(ctmatch.counter_imm(
+                        ct::CounterKey {
+                            ctns: "irq",
+                            key: key,
+                        },
+                        count,
+                        &ct::unit_units_ps(),
+                    ));
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
+
+struct IrqParser {}
+
+impl Parser for IrqParser {
+    // Syntax: [cpu cpumatch [cpumatch ...] ...] [total] ctmatch* [ctmatch* ...]
+    fn parse(
+        &self,
+        words: &mut Peekable<std::slice::Iter<String>>,
+    ) -> Result<Vec<Box<dyn ct::CounterRule>>, String> {
+        if words.peek().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let cpumatches = parse_cpumatches(words)?;
+        let total = words.peek().map(|word| *word == "total") == Some(true);
+        if total {
+            words.next();
+        }
+        let ctmatches = parse_ctmatches(words)?;
+
+        let ret: Vec<Box<dyn ct::CounterRule>> = vec![Box::new(IrqCounterRule {
+            cpumatches: cpumatches,
+            total: total,
+            ctmatches: ctmatches,
+        })];
+        Ok(ret)
+    }
+}
+
+#[derive(Debug)]
+struct ConntrackCounterRule {
+    cpumatches: Vec<CpuMatch>,
+    total: bool,
+    ctmatches: Vec<CounterNameMatch>,
+}
+
+impl ct::CounterRule for ConntrackCounterRule {
+    fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
+        let mut ret = Vec::new();
+        let mut totals: Vec<(CounterMatch, conntrack::ConntrackStat)> = Vec::new();
+        for ct_stat in conntrack::stats()? {
+            let ctmatch = match find_ctmatch(&self.ctmatches, &ct_stat.name) {
+                Some(ctmatch) => ctmatch,
+                None => continue,
+            };
+
+            let captures = ct_stat.cpu.map(|cpu| find_cpumatch(&self.cpumatches, cpu));
+            match (ct_stat.cpu, captures) {
+                (Some(_), Some(None)) => {}
+                (Some(_), _) if self.total => {
+                    match totals
+                        .iter_mut()
+                        .find(|(_, total)| total.name == ct_stat.name)
+                    {
+                        Some((_, total)) => total.value += ct_stat.value,
+                        None => totals.push((ctmatch, ct_stat)),
+                    }
+                }
+                (Some(cpu), Some(Some(captures))) => {
+                    let mut key = cpu_key(cpu, &captures);
+                    key.push((ct::KeyHead::Name, ct_stat.name));
+                    ret.push(ctmatch.counter_imm(
+                        ct::CounterKey {
+                            ctns: "conntrack",
+                            key: key,
+                        },
+                        ct_stat.value,
+                        &ct_stat.default_unit,
+                    ))
+                }
+                _ => totals.push((ctmatch, ct_stat)),
+            }
+        }
+
+        // Sums over the selected CPUs, and the global statistics.
+        for (ctmatch, ct_stat) in totals {
+            ret.push(ctmatch.counter_imm(
+                ct::CounterKey {
+                    ctns: "conntrack",
+                    key: vec![(ct::KeyHead::Name, ct_stat.name)],
+                },
+                ct_stat.value,
+                &ct_stat.default_unit,
+            ));
+        }
+        Ok(ret)
+    }
+}
+
+struct ConntrackParser {}
+
+impl Parser for ConntrackParser {
+    // Syntax: [cpu cpumatch [cpumatch ...] ...] [total] ctmatch* [ctmatch* ...]
+    fn parse(
+        &self,
+        words: &mut Peekable<std::slice::Iter<String>>,
+    ) -> Result<Vec<Box<dyn ct::CounterRule>>, String> {
+        if words.peek().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let cpumatches = parse_cpumatches(words)?;
+        let total = words.peek().map(|word| *word == "total") == Some(true);
+        if total {
+            words.next();
+        }
+        let ctmatches = parse_ctmatches(words)?;
+
+        let ret: Vec<Box<dyn ct::CounterRule>> = vec![Box::new(ConntrackCounterRule {
+            cpumatches: cpumatches,
+            total: total,
+            ctmatches: ctmatches,
+        })];
+        Ok(ret)
+    }
+}
+
+#[derive(Debug)]
+struct TrapCounterRule {
+    devmatches: Vec<NamePattern>,
+    ctmatches: Vec<CounterNameMatch>,
+}
+
+impl ct::CounterRule for TrapCounterRule {
+    fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
+        let mut ret = Vec::new();
+        for trap_stat in devlink::trap_stats()? {
+            if !self
+                .devmatches
+                .iter()
+                .any(|ref pat| pat.matches(&trap_stat.dev))
+            {
+                continue;
+            }
+
+            // Counter matches select traps, trap groups and policers, not their statistics.
+            let mut key = vec![(ct::KeyHead::Dev, trap_stat.dev)];
+            let object = if let Some(trap) = trap_stat.trap {
+                key.push((ct::KeyHead::Group, trap_stat.group.unwrap_or_default()));
+                key.push((ct::KeyHead::Trap, trap.clone()));
+                trap
+            } else if let Some(group) = trap_stat.group {
+                key.push((ct::KeyHead::Group, group.clone()));
+                group
+            } else if let Some(policer) = trap_stat.policer {
+                key.push((ct::KeyHead::Policer, policer.to_string()));
+                policer.to_string()
+            } else {
+                continue;
+            };
+            key.push((ct::KeyHead::Name, trap_stat.name));
+
+            if let Some(ctmatch) = find_ctmatch(&self.ctmatches, &object) {
+                let mut imm = ctmatch.counter_imm(
+                    ct::CounterKey {
+                        ctns: "trap",
+                        key: key,
+                    },
+                    trap_stat.value,
+                    &trap_stat.default_unit,
+                );
+                // So a unit given for a trap only applies to the statistics where it makes sense,
+                // e.g. /pps to rx_packets, but not rx_bytes.
+                if !imm.unit.same_dimension(&trap_stat.default_unit) {
+                    imm.unit = trap_stat.default_unit;
+                }
+                ret.push(imm);
+            }
+        }
+        Ok(ret)
+    }
+}
+
+struct TrapParser {}
+
+impl Parser for TrapParser {
+    // Syntax: [devmatch [devmatch ...]] ctmatch* [ctmatch* ...]
+    fn parse(
+        &self,
+        words: &mut Peekable<std::slice::Iter<String>>,
+    ) -> Result<Vec<Box<dyn ct::CounterRule>>, String> {
+        if words.peek().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let devmatches = parse_devmatches(words)?;
+        let ctmatches = parse_ctmatches(words)?;
+
+        let ret: Vec<Box<dyn ct::CounterRule>> = vec![Box::new(TrapCounterRule {
+            devmatches: devmatches,
+            ctmatches: ctmatches,
+        })];
+        Ok(ret)
+    }
+}
+
+#[derive(Debug)]
+struct ResourceCounterRule {
+    devmatches: Vec<NamePattern>,
+    resmatches: Vec<NamePattern>,
+    ctmatches: Vec<CounterNameMatch>,
+}
+
+impl ct::CounterRule for ResourceCounterRule {
+    fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
+        let mut ret = Vec::new();
+        for res_stat in devlink::resource_stats()? {
+            if !self
+                .devmatches
+                .iter()
+                .any(|ref pat| pat.matches(&res_stat.dev))
+                || !self
+                    .resmatches
+                    .iter()
+                    .any(|ref pat| pat.matches(&res_stat.path))
+            {
+                continue;
+            }
+
+            let mut counters = vec![("size", res_stat.size)];
+            if let Some(occ) = res_stat.occ {
+                counters.push(("occ", occ));
+            }
+
+            for (name, value) in counters {
+                if let Some(ctmatch) = find_ctmatch(&self.ctmatches, name) {
+                    let default_unit = ct::unit_units();
+                    let mut imm = ctmatch.counter_imm(
+                        ct::CounterKey {
+                            ctns: "resource",
+                            key: vec![
+                                (ct::KeyHead::Dev, res_stat.dev.clone()),
+                                (ct::KeyHead::Resource, res_stat.path.clone()),
+                                (ct::KeyHead::Name, name.to_string()),
+                            ],
+                        },
+                        value,
+                        &default_unit,
+                    );
+
+                    // A share such as /% is of the resource size, unless the unit gives its own
+                    // limit. A bare /% is converted from the default unit.
+                    let share = imm.unit.units.last().unwrap().base.is_ratio();
+                    if share && imm.unit.limit.is_none() && res_stat.size != 0 {
+                        if imm.unit.units[0].base.is_ratio() {
+                            imm.unit.units.insert(0, default_unit.units[0].clone());
+                        }
+                        imm.unit.limit = Some(ct::Value::from_num(res_stat.size));
+                    } else if !imm.unit.same_dimension(&default_unit) {
+                        imm.unit = default_unit;
+                    }
+                    ret.push(imm);
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
+
+struct ResourceParser {}
+
+impl Parser for ResourceParser {
+    // Syntax: [devmatch [devmatch ...]] [resmatch [resmatch ...]] ctmatch* [ctmatch* ...]
+    fn parse(
+        &self,
+        words: &mut Peekable<std::slice::Iter<String>>,
+    ) -> Result<Vec<Box<dyn ct::CounterRule>>, String> {
+        if words.peek().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let devmatches = parse_devmatches(words)?;
+        let resmatches = parse_resmatches(words)?;
+        let ctmatches = parse_ctmatches(words)?;
+
+        let ret: Vec<Box<dyn ct::CounterRule>> = vec![Box::new(ResourceCounterRule {
+            devmatches: devmatches,
+            resmatches: resmatches,
+            ctmatches: ctmatches,
+        })];
+        Ok(ret)
+    }
+}
+
+#[derive(Debug)]
+struct NftCounterRule {
+    families: Vec<NamePattern>,
+    tables: Vec<NamePattern>,
+    chains: Vec<NamePattern>,
+    rules: Vec<NamePattern>,
+    ctmatches: Vec<CounterNameMatch>,
+}
+
+// An empty list of selectors matches everything.
+fn matches_any(pats: &Vec<NamePattern>, name: &str) -> bool {
+    pats.is_empty() || pats.iter().any(|pat| pat.matches(name))
+}
+
+impl ct::CounterRule for NftCounterRule {
+    fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
+        let mut ret = Vec::new();
+        for nft_stat in nftables::stats()? {
+            if !matches_any(&self.families, &nft_stat.family)
+                || !matches_any(&self.tables, &nft_stat.table)
+            {
+                continue;
+            }
+
+            let mut key = vec![
+                (ct::KeyHead::Family, nft_stat.family),
+                (ct::KeyHead::Table, nft_stat.table),
+            ];
+            if let Some(obj) = nft_stat.obj {
+                // Named counters are not in any chain, so a chain selector excludes them. Rule
+                // selectors match the counter name.
+                if !self.chains.is_empty() || !matches_any(&self.rules, &obj) {
+                    continue;
+                }
+                key.push((ct::KeyHead::Object, obj));
+            } else {
+                let chain = nft_stat.chain.unwrap_or_default();
+                let rule = nft_stat.rule.unwrap_or_default();
+                if !matches_any(&self.chains, &chain) || !matches_any(&self.rules, &rule) {
+                    continue;
+                }
+                key.push((ct::KeyHead::Chain, chain));
+                key.push((ct::KeyHead::Rule, rule));
+            }
+
+            if let Some(ctmatch) = find_ctmatch(&self.ctmatches, &nft_stat.name) {
+                key.push((ct::KeyHead::Name, nft_stat.name));
+                ret.push(ctmatch.counter_imm(
+                    ct::CounterKey {
+                        ctns: "nft",
+                        key: key,
+                    },
+                    nft_stat.value,
+                    &nft_stat.default_unit,
+                ));
+            }
+        }
+        Ok(ret)
+    }
+}
+
+static NFT_KEYWORDS: [&str; 4] = ["family", "table", "chain", "rule"];
+
+struct NftParser {}
+
+impl Parser for NftParser {
+    // Syntax: [family glob] [table glob] [chain glob] [rule glob] ctmatch* [ctmatch* ...]
+    //
+    // Each keyword takes one glob, and can be given several times. Rules are matched by comment,
+    // or by handle if they have no comment.
+    fn parse(
+        &self,
+        words: &mut Peekable<std::slice::Iter<String>>,
+    ) -> Result<Vec<Box<dyn ct::CounterRule>>, String> {
+        if words.peek().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let mut rule = NftCounterRule {
+            families: Vec::new(),
+            tables: Vec::new(),
+            chains: Vec::new(),
+            rules: Vec::new(),
+            ctmatches: Vec::new(),
+        };
+        while let Some(keyword) = words.peek() {
+            let keyword = keyword.as_str();
+            if !NFT_KEYWORDS.contains(&keyword) {
+                break;
+            }
+            words.next();
+            let pat = match words.peek() {
+                Some(word) => NamePattern::new(word)?,
+                None => return Err(format!("Expected a pattern after {}", keyword)),
+            };
+            words.next();
+            match keyword {
+                "family" => rule.families.push(pat),
+                "table" => rule.tables.push(pat),
+                "chain" => rule.chains.push(pat),
+                _ => rule.rules.push(pat),
+            }
+        }
+        rule.ctmatches = parse_ctmatches(words)?;
+
+        let ret: Vec<Box<dyn ct::CounterRule>> = vec![Box::new(rule)];
+        Ok(ret)
+    }
+}
+
+#[derive(Debug)]
+struct AddrMatch {
+    addr: IpAddr,
+    len: u32,
+}
+
+impl AddrMatch {
+    fn matches(&self, addr: &IpAddr) -> bool {
+        let (want, have, bits) = match (self.addr, addr) {
+            (IpAddr::V4(want), IpAddr::V4(have)) => {
+                (u32::from(want) as u128, u32::from(*have) as u128, 32)
+            }
+            (IpAddr::V6(want), IpAddr::V6(have)) => (u128::from(want), u128::from(*have), 128),
+            _ => return false,
+        };
+        self.len == 0 || (want ^ have) >> (bits - self.len) == 0
+    }
+}
+
+// Syntax: addr[/len]
+fn parse_addrmatch(word: &String) -> Result<AddrMatch, String> {
+    let mut parts = word.splitn(2, '/');
+    let addr = parts
+        .next()
+        .unwrap()
+        .parse::<IpAddr>()
+        .map_err(|_| format!("Invalid address: {}", word))?;
+    let max_len = if addr.is_ipv4() { 32 } else { 128 };
+    let len = match parts.next() {
+        Some(len) => match len.parse::<u32>() {
+            Ok(len) if len <= max_len => len,
+            _ => return Err(format!("Invalid prefix length: {}", word)),
+        },
+        None => max_len,
+    };
+    Ok(AddrMatch {
+        addr: addr,
+        len: len,
+    })
+}
+
+// Cgroups are given either by ID, or by path, absolute or relative to the cgroup2 mount. The ID of
+// a cgroup is the inode number of its directory.
+fn parse_cgroupmatch(word: &String) -> Result<u64, String> {
+    if let Ok(id) = word.parse::<u64>() {
+        return Ok(id);
+    }
+    let path = std::path::Path::new("/sys/fs/cgroup").join(word);
+    std::fs::metadata(&path)
+        .map(|md| md.ino())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[derive(Debug)]
+struct SockCounterRule {
+    locals: Vec<AddrMatch>,
+    remotes: Vec<AddrMatch>,
+    lports: Vec<u16>,
+    rports: Vec<u16>,
+    cgroups: Vec<u64>,
+    ctmatches: Vec<CounterNameMatch>,
+}
+
+impl SockCounterRule {
+    fn matches(&self, sock_stat: &inet_diag::SockStat) -> bool {
+        (self.locals.is_empty()
+            || self
+                .locals
+                .iter()
+                .any(|addrmatch| addrmatch.matches(&sock_stat.local.ip())))
+            && (self.remotes.is_empty()
+                || self
+                    .remotes
+                    .iter()
+                    .any(|addrmatch| addrmatch.matches(&sock_stat.remote.ip())))
+            && (self.lports.is_empty() || self.lports.contains(&sock_stat.local.port()))
+            && (self.rports.is_empty() || self.rports.contains(&sock_stat.remote.port()))
+            && (self.cgroups.is_empty()
+                || sock_stat
+                    .cgroup
+                    .map_or(false, |cgroup| self.cgroups.contains(&cgroup)))
+    }
+}
+
+impl ct::CounterRule for SockCounterRule {
+    fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
+        let mut ret = Vec::new();
+        for sock_stat in inet_diag::tcp_stats()? {
+            if !self.matches(&sock_stat) {
+                continue;
+            }
+            if let Some(ctmatch) = find_ctmatch(&self.ctmatches, &sock_stat.name) {
+                ret.push(ctmatch.counter_imm(
+                    ct::CounterKey {
+                        ctns: "sock",
+                        key: vec![
+                            (ct::KeyHead::Local, sock_stat.local.to_string()),
+                            (ct::KeyHead::Remote, sock_stat.remote.to_string()),
+                            (ct::KeyHead::Name, sock_stat.name),
+                        ],
+                    },
+                    sock_stat.value,
+                    &sock_stat.default_unit,
+                ));
+            }
+        }
+        Ok(ret)
+    }
+}
+
+static SOCK_KEYWORDS: [&str; 5] = ["local", "remote", "lport", "rport", "cgroup"];
+
+struct SockParser {}
+
+impl Parser for SockParser {
+    // Syntax: [local addr[/len]] [remote addr[/len]] [lport port] [rport port] [cgroup cgroup]
+    //         ctmatch* [ctmatch* ...]
+    //
+    // Each keyword takes one argument, and can be given several times. They only select sockets;
+    // rows are always per socket.
+    fn parse(
+        &self,
+        words: &mut Peekable<std::slice::Iter<String>>,
+    ) -> Result<Vec<Box<dyn ct::CounterRule>>, String> {
+        if words.peek().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let mut rule = SockCounterRule {
+            locals: Vec::new(),
+            remotes: Vec::new(),
+            lports: Vec::new(),
+            rports: Vec::new(),
+            cgroups: Vec::new(),
+            ctmatches: Vec::new(),
+        };
+        while let Some(keyword) = words.peek() {
+            let keyword = keyword.as_str();
+            if !SOCK_KEYWORDS.contains(&keyword) {
+                break;
+            }
+            words.next();
+            let word = match words.peek() {
+                Some(word) => *word,
+                None => return Err(format!("Expected an argument after {}", keyword)),
+            };
+            let parse_port = |word: &String| {
+                word.parse::<u16>()
+                    .map_err(|_| format!("Invalid port: {}", word))
+            };
+            match keyword {
+                "local" => rule.locals.push(parse_addrmatch(word)?),
+                "remote" => rule.remotes.push(parse_addrmatch(word)?),
+                "lport" => rule.lports.push(parse_port(word)?),
+                "rport" => rule.rports.push(parse_port(word)?),
+                _ => rule.cgroups.push(parse_cgroupmatch(word)?),
+            }
+            words.next();
+        }
+        rule.ctmatches = parse_ctmatches(words)?;
+
+        let ret: Vec<Box<dyn ct::CounterRule>> = vec![Box::new(rule)];
+        Ok(ret)
+    }
+}
+
+#[derive(Debug)]
+struct PagePoolCounterRule {
+    ifmatches: Vec<IfnameMatch>,
+    pools: Vec<u64>,
+    ctmatches: Vec<CounterNameMatch>,
+}
+
+impl ct::CounterRule for PagePoolCounterRule {
+    fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
+        let mut ret = Vec::new();
+        let ifmatcher = IfnameMatcher::new(&self.ifmatches);
+        for pp_stat in page_pool::stats()? {
+            if !ifmatcher.matches(&pp_stat.ifname)
+                || !(self.pools.is_empty() || self.pools.contains(&pp_stat.id))
+            {
+                continue;
+            }
+            if let Some(ctmatch) = find_ctmatch(&self.ctmatches, &pp_stat.name) {
+                let mut key = ifmatcher.key(&pp_stat.ifname);
+                key.push((ct::KeyHead::Pool, pp_stat.id.to_string()));
+                key.push((ct::KeyHead::Name, pp_stat.name));
+                ret.push(ctmatch.counter_imm(
+                    ct::CounterKey {
+                        ctns: "pp",
+                        key: key,
+                    },
+                    pp_stat.value,
+                    &pp_stat.default_unit,
+                ));
+            }
+        }
+        Ok(ret)
+    }
+}
+
+struct PagePoolParser {}
+
+impl Parser for PagePoolParser {
+    // Syntax: [@ifmatch* [@if2match* ...]] [pool id [pool id ...]] ctmatch* [ctmatch* ...]
+    fn parse(
+        &self,
+        words: &mut Peekable<std::slice::Iter<String>>,
+    ) -> Result<Vec<Box<dyn ct::CounterRule>>, String> {
+        if words.peek().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let ifmatches = match peek_ifmatch(words)? {
+            Some(_) => parse_ifmatches(words)?,
+            None => vec![any_ifname()],
+        };
+
+        let mut pools = Vec::new();
+        while words.peek().map(|word| *word == "pool") == Some(true) {
+            words.next();
+            match words.peek().map(|word| word.parse::<u64>()) {
+                Some(Ok(id)) => pools.push(id),
+                _ => return Err("Expected a page pool ID after pool".to_string()),
+            }
+            words.next();
+        }
+
+        let ret: Vec<Box<dyn ct::CounterRule>> = vec![Box::new(PagePoolCounterRule {
+            ifmatches: ifmatches,
+            pools: pools,
+            ctmatches: parse_ctmatches(words)?,
+        })];
+        Ok(ret)
+    }
+}
+
+#[derive(Debug)]
+struct RdmaCounterRule {
+    devs: Vec<NamePattern>,
+    ports: Vec<NamePattern>,
+    ctmatches: Vec<CounterNameMatch>,
+}
+
+impl ct::CounterRule for RdmaCounterRule {
+    fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
+        let mut ret = Vec::new();
+        for rdma_stat in rdma::stats()? {
+            if !matches_any(&self.devs, &rdma_stat.dev)
+                || !matches_any(&self.ports, &rdma_stat.port)
+            {
+                continue;
+            }
+            if let Some(ctmatch) = find_ctmatch(&self.ctmatches, &rdma_stat.name) {
+                ret.push(ctmatch.counter_imm(
+                    ct::CounterKey {
+                        ctns: "rdma",
+                        key: vec![
+                            (ct::KeyHead::Dev, rdma_stat.dev),
+                            (ct::KeyHead::Port, rdma_stat.port),
+                            (ct::KeyHead::Name, rdma_stat.name),
+                        ],
+                    },
+                    rdma_stat.value,
+                    &rdma_stat.default_unit,
+                ));
+            }
+        }
+        Ok(ret)
+    }
+}
+
+static RDMA_KEYWORDS: [&str; 2] = ["dev", "port"];
+
+struct RdmaParser {}
+
+impl Parser for RdmaParser {
+    // Syntax: [dev glob] [port glob] ctmatch* [ctmatch* ...]
+    //
+    // Each keyword takes one glob, and can be given several times.
+    fn parse(
+        &self,
+        words: &mut Peekable<std::slice::Iter<String>>,
+    ) -> Result<Vec<Box<dyn ct::CounterRule>>, String> {
+        if words.peek().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let mut rule = RdmaCounterRule {
+            devs: Vec::new(),
+            ports: Vec::new(),
+            ctmatches: Vec::new(),
+        };
+        while let Some(keyword) = words.peek() {
+            let keyword = keyword.as_str();
+            if !RDMA_KEYWORDS.contains(&keyword) {
+                break;
+            }
+            words.next();
+            let pat = match words.peek() {
+                Some(word) => NamePattern::new(word)?,
+                None => return Err(format!("Expected a pattern after {}", keyword)),
+            };
+            words.next();
+            match keyword {
+                "dev" => rule.devs.push(pat),
+                _ => rule.ports.push(pat),
+            }
+        }
+        rule.ctmatches = parse_ctmatches(words)?;
+
+        let ret: Vec<Box<dyn ct::CounterRule>> = vec![Box::new(rule)];
+        Ok(ret)
+    }
+}
+
+#[derive(Debug)]
+struct FileMatch {
+    path: String,
+    field: Option<usize>,
+    ctmatch: CounterNameMatch,
+}
+
+#[derive(Debug)]
+struct FileCounterRule {
+    filematches: Vec<FileMatch>,
+}
+
+impl ct::CounterRule for FileCounterRule {
+    fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
+        let mut ret = Vec::new();
+        for filematch in &self.filematches {
+            for file_stat in file::stats(&filematch.path, filematch.field)? {
+                let mut key: Vec<(ct::KeyHead, String)> = file_stat
+                    .captures
+                    .into_iter()
+                    .enumerate()
+                    .map(|(n, capture)| (ct::KeyHead::Capture(n), capture))
+                    .collect();
+                // Fields of the same file are told apart by their number.
+                let name = match filematch.field {
+                    Some(field) => format!("{}:{}", file_stat.name, field),
+                    None => file_stat.name,
+                };
+                key.push((ct::KeyHead::Name, name));
+                ret.push(filematch.ctmatch.counter_imm(
+                    ct::CounterKey {
+                        ctns: "file",
+                        key: key,
+                    },
+                    file_stat.value,
+                    &ct::unit_units_ps(),
+                ));
+            }
+        }
+        Ok(ret)
+    }
+}
+
+struct FileParser {}
+
+impl Parser for FileParser {
+    // Syntax: path[:field] [non0] [unit] [path[:field] ...]
+    //
+    // Paths are globs, and start with a '/' just like units do. Only the word after a path and its
+    // filters can be a unit, and if it parses as a unit chain, it is taken as a unit of the
+    // preceding paths that don't have one yet. Anything else is taken as a path.
+    fn parse(
+        &self,
+        words: &mut Peekable<std::slice::Iter<String>>,
+    ) -> Result<Vec<Box<dyn ct::CounterRule>>, String> {
+        let mut filematches = Vec::<FileMatch>::new();
+        let mut after_path = false;
+        while let Some(word) = words.peek() {
+            if is_ns(word) {
+                break;
+            }
+
+            if after_path && is_unit(word) {
+                if let Ok(u) = parse_unit_chain(word) {
+                    for filematch in filematches.iter_mut().rev() {
+                        if filematch.ctmatch.unit.is_none() {
+                            filematch.ctmatch.unit = Some(u.clone());
+                        } else {
+                            break;
+                        }
+                    }
+                    after_path = false;
+                    words.next();
+                    continue;
+                }
+            }
+
+            if !word.starts_with('/') {
+                return Err(format!("Expected an absolute path: {}", word));
+            }
+            let (path, field) = file::split_field(word);
+            file::check_pattern(path)?;
+            if file::capture_count(path) > ct::MAX_CAPTURES {
+                return Err(format!("Too many wildcards: {}", word));
+            }
+            let mut filematch = FileMatch {
+                path: path.to_string(),
+                field: field,
+                ctmatch: CounterNameMatch {
+                    pat: NamePattern::Glob(
+                        glob::Pattern::new(word).map_err(|err| err.msg.to_string())?,
+                    ),
+                    exclude: false,
+                    unit: None,
+                    vfilt: Vec::new(),
+                },
+            };
+            words.next();
+
+            while let Some(vf) = parse_value_filter(words)? {
+                filematch.ctmatch.vfilt.push(vf);
+            }
+            filematches.push(filematch);
+            after_path = true;
+        }
+
+        if filematches.is_empty() {
+            return Err("file: expects a path".to_string());
+        }
+        let ret: Vec<Box<dyn ct::CounterRule>> = vec![Box::new(FileCounterRule {
+            filematches: filematches,
+        })];
+        Ok(ret)
+    }
+}
+
+#[derive(Debug)]
+struct ExecCounterRule {
+    command: String,
+    runner: exec::Runner,
+    ctmatches: Vec<CounterNameMatch>,
+}
+
+impl ct::CounterRule for ExecCounterRule {
+    fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
+        let mut ret = Vec::new();
+        for exec_stat in self.runner.poll()? {
+            if let Some(ctmatch) = find_ctmatch(&self.ctmatches, &exec_stat.name) {
+                ret.push(ctmatch.counter_imm(
+                    ct::CounterKey {
+                        ctns: "exec",
+                        key: vec![
+                            (ct::KeyHead::Command, self.command.clone()),
+                            (ct::KeyHead::Name, exec_stat.name),
+                        ],
+                    },
+                    exec_stat.value,
+                    &ct::unit_units_ps(),
+                ));
+            }
+        }
+        Ok(ret)
+    }
+}
+
+const EXEC_DEFAULT_TIMEOUT_MS: u64 = 1000;
+
+struct ExecParser {}
+
+impl Parser for ExecParser {
+    // Syntax: [timeout ms] command ctmatch* [ctmatch* ...]
+    //
+    // The command is a single word, which is passed to sh -c. It should print lines of
+    // "name value", or JSON.
+    fn parse(
+        &self,
+        words: &mut Peekable<std::slice::Iter<String>>,
+    ) -> Result<Vec<Box<dyn ct::CounterRule>>, String> {
+        if words.peek().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let mut timeout_ms = EXEC_DEFAULT_TIMEOUT_MS;
+        if words.peek().map(|word| *word == "timeout") == Some(true) {
+            words.next();
+            timeout_ms = match words.peek().map(|word| word.parse::<u64>()) {
+                Some(Ok(ms)) => ms,
+                _ => return Err("Expected a number of milliseconds after timeout".to_string()),
+            };
+            words.next();
+        }
+
+        let command = match words.peek() {
+            Some(word) if !is_ns(word) => word.to_string(),
+            _ => return Err("Expected a command".to_string()),
+        };
+        words.next();
+        let ret: Vec<Box<dyn ct::CounterRule>> = vec![Box::new(ExecCounterRule {
+            runner: exec::Runner::new(&command, std::time::Duration::from_millis(timeout_ms)),
+            command: command,
+            ctmatches: parse_ctmatches(words)?,
+        })];
+        Ok(ret)
+    }
+}
+
+const PARSERS: [(&str, &dyn Parser); 16] = [
+    ("conntrack", &ConntrackParser {}),
+    ("ethtool", &EthtoolParser {}),
+    ("exec", &ExecParser {}),
+    ("file", &FileParser {}),
+    ("irq", &IrqParser {}),
+    ("link", &LinkParser {}),
+    ("nft", &NftParser {}),
+    ("pp", &PagePoolParser {}),
+    ("qdisc", &QdiscParser {}),
+    ("rdma", &RdmaParser {}),
+    ("resource", &ResourceParser {}),
+    ("snmp", &SnmpParser {}),
+    ("sock", &SockParser {}),
+    ("softnet", &SoftnetParser {}),
+    ("trap", &TrapParser {}),
+    ("xdp", &XdpParser {}),
+];
+
+// A misplaced word is likely a misspelled namespace, or an interface name without the '@'.
+fn word_candidates() -> Vec<String> {
+    let mut candidates: Vec<String> = PARSERS
+        .iter()
+        .map(|(name, _)| format!("{}:", name))
+        .collect();
+    candidates.extend(
+        netlink::ifnames()
+            .iter()
+            .map(|ifname| format!("@{}", ifname)),
+    );
+    candidates
+}
+
+pub fn parse_expr(
+    words: &mut Peekable<std::slice::Iter<String>>,
+) -> Result<Vec<Box<dyn ct::CounterRule>>, ParseError> {
+    let all: Vec<String> = words.clone().cloned().collect();
+    let mut ret = Vec::new();
+    loop {
+        let at = all.len() - words.len();
+        let ns = parse_ns_opt(words).unwrap_or("ethtool".to_string());
+        let parser = match PARSERS.iter().find(|(name, _)| *name == ns) {
+            Some((_, parser)) => parser,
+            None => {
+                return Err(
+                    ParseError::new(&all, at, format!("Unknown namespace: {}", ns))
+                        .suggest(&all, &word_candidates()),
+                )
+            }
+        };
+        let mut nv = parser.parse(words).map_err(|message| {
+            ParseError::new(&all, all.len() - words.len(), message)
+                .suggest(&all, &word_candidates())
+        })?;
+        if nv.is_empty() {
+            break;
+        }
+        ret.append(&mut nv);
+    }
+    Ok(ret)
+}
+
+// Names that a rule's counter matches give literally, but that none of its counters has, are likely
+// typos if they are close to a name that one has. Such names are returned as warnings, pointing at
+// the word in the expression. Rules come in the order of the expression, and so do their counter
+// matches, so each name is looked for after the previous one.
+pub fn check_names(words: &[String], rules: &Vec<Box<dyn ct::CounterRule>>) -> Vec<ParseError> {
+    let mut warnings = Vec::new();
+    let mut at = 0;
+    for rule in rules {
+        let plain = rule.plain_names();
+        if plain.is_empty() {
+            continue;
+        }
+        let names = rule.names();
+        for name in plain {
+            // Exclusions are written as !name.
+            let idx = match words[at..]
+                .iter()
+                .position(|word| *word == name || word.strip_prefix('!') == Some(&name))
+            {
+                Some(pos) => at + pos,
+                None => continue,
+            };
+            at = idx + 1;
+            if names.contains(&name) {
+                continue;
+            }
+            let mut warning = ParseError::new(words, idx, format!("No counter named {}", name));
+            warning.suggestion = closest(&name, &names).cloned();
+            if warning.suggestion.is_some() {
+                warnings.push(warning);
+            }
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(expr: &str) -> Vec<String> {
+        expr.split_whitespace()
+            .map(|word| word.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn ctmatches_unit_after_exclusion() {
+        let words = words("rx_* !rx_*_prio_* /pps");
+        let ctmatches = parse_ctmatches(&mut words.iter().peekable()).unwrap();
+        assert_eq!(ctmatches.len(), 2);
+        assert!(!ctmatches[0].exclude);
+        let unit = ctmatches[0].unit.as_ref().unwrap();
+        assert_eq!(unit.freq, ct::UFreq::PerSecond);
+        assert_eq!(unit.units[0].base, ct::UBase::Packets);
+        assert!(ctmatches[1].exclude);
+        assert!(ctmatches[1].unit.is_none());
+        assert!(find_ctmatch(&ctmatches, "rx_packets").is_some());
+        assert!(find_ctmatch(&ctmatches, "rx_bytes_prio_1").is_none());
+    }
+
+    #[test]
+    fn ctmatches_filter_on_exclusion() {
+        let words = words("rx_* !rx_bytes non0");
+        let mut it = words.iter().peekable();
+        let err = parse_ctmatches(&mut it).unwrap_err();
+        assert!(err.contains("exclusion"), "{}", err);
+        assert_eq!(it.peek().map(|word| word.as_str()), Some("non0"));
+    }
+
+    #[test]
+    fn threshold_freq() {
+        let threshold = parse_threshold(&">1G/bps".to_string()).unwrap().unwrap();
+        assert_eq!(threshold.freq, Some(ct::UFreq::PerSecond));
+        assert_eq!(threshold.base, Some(ct::UBase::Bits));
+        let threshold = parse_threshold(&">1M/dB".to_string()).unwrap().unwrap();
+        assert_eq!(threshold.freq, Some(ct::UFreq::Delta));
+        let threshold = parse_threshold(&">1G/b".to_string()).unwrap().unwrap();
+        assert_eq!(threshold.freq, None);
+    }
+
+    #[test]
+    fn threshold() {
+        let threshold = parse_threshold(&">1K".to_string()).unwrap().unwrap();
+        assert!(!threshold.avg);
+        assert_eq!(threshold.op, ct::ThresholdOp::Gt);
+        assert_eq!(threshold.threshold, ct::Value::from_num(1000));
+        assert_eq!(threshold.base, None);
+
+        let threshold = parse_threshold(&"avg<=1.5Mi".to_string()).unwrap().unwrap();
+        assert!(threshold.avg);
+        assert_eq!(threshold.op, ct::ThresholdOp::Le);
+        assert_eq!(threshold.threshold, ct::Value::from_num(3 << 19));
+
+        // The prefix of the unit scales the threshold too.
+        let threshold = parse_threshold(&"=2K/KB".to_string()).unwrap().unwrap();
+        assert_eq!(threshold.op, ct::ThresholdOp::Eq);
+        assert_eq!(threshold.threshold, ct::Value::from_num(2_000_000));
+        assert_eq!(threshold.base, Some(ct::UBase::Bytes));
+
+        // The default unit placeholder compares in the counter's unit.
+        let threshold = parse_threshold(&">5/xps".to_string()).unwrap().unwrap();
+        assert_eq!(threshold.threshold, ct::Value::from_num(5));
+        assert_eq!(threshold.base, None);
+
+        assert!(parse_threshold(&"rx_bytes".to_string()).unwrap().is_none());
+        assert!(parse_threshold(&">".to_string()).is_err());
+        assert!(parse_threshold(&">1X".to_string()).is_err());
+        assert!(parse_threshold(&">1K/q".to_string()).is_err());
+    }
+
+    #[test]
+    fn spike() {
+        let spike = parse_spike(&"spike".to_string()).unwrap().unwrap();
+        assert_eq!(spike.factor, ct::Value::from_num(DEFAULT_SPIKE_FACTOR));
+        let spike = parse_spike(&"spike>2.5".to_string()).unwrap().unwrap();
+        assert_eq!(spike.factor, ct::Value::from_num(2.5));
+        assert!(parse_spike(&"spiky".to_string()).unwrap().is_none());
+        assert!(parse_spike(&"spike>0".to_string()).is_err());
+        assert!(parse_spike(&"spike>x".to_string()).is_err());
+    }
+
+    #[test]
+    fn error_unknown_namespace() {
+        let words = words("qdsic: @eth0");
+        let err = parse_expr(&mut words.iter().peekable()).unwrap_err();
+        assert_eq!(err.word, 0);
+        assert_eq!(
+            err.to_string(),
+            "Unknown namespace: qdsic, did you mean `qdisc:`?"
+        );
+        assert_eq!(err.show(&words), "qdsic: @eth0\n^^^^^^");
+    }
+
+    #[test]
+    fn error_in_rule() {
+        // The second rule fails at its second word.
+        let words = words("file: /proc/loadavg file: loadavg");
+        let err = parse_expr(&mut words.iter().peekable()).unwrap_err();
+        assert_eq!(err.word, 3);
+        assert_eq!(err.to_string(), "Expected an absolute path: loadavg");
+        assert_eq!(
+            err.show(&words),
+            "file: /proc/loadavg file: loadavg\n                          ^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn error_at_end() {
+        // Past the last word, the caret is one character wide.
+        let words = words("file:");
+        let err = parse_expr(&mut words.iter().peekable()).unwrap_err();
+        assert_eq!(err.word, 1);
+        assert_eq!(err.to_string(), "file: expects a path");
+        assert_eq!(err.show(&words), "file:\n      ^");
+    }
+
+    #[derive(Debug)]
+    struct NamesRule {
+        plain: Vec<String>,
+        names: Vec<String>,
+    }
+
+    impl ct::CounterRule for NamesRule {
+        fn counters(&self) -> Result<Vec<ct::CounterImm>, String> {
+            Ok(Vec::new())
+        }
+        fn names(&self) -> Vec<String> {
+            self.names.clone()
+        }
+        fn plain_names(&self) -> Vec<String> {
+            self.plain.clone()
+        }
+    }
+
+    #[test]
+    fn names_suggestions() {
+        let words = words("ethtool: @eth0 rx_pakets !tx_packets foo ethtool: @eth1 rx_pakets");
+        let rule = |plain: &[&str]| -> Box<dyn ct::CounterRule> {
+            Box::new(NamesRule {
+                plain: plain.iter().map(|name| name.to_string()).collect(),
+                names: vec!["rx_packets".to_string(), "tx_packets".to_string()],
+            })
+        };
+        // Names that exist, or aren't close to any that does, are left alone.
+        let rules = vec![
+            rule(&["rx_pakets", "tx_packets", "foo"]),
+            rule(&["rx_pakets"]),
+        ];
+        let warnings = check_names(&words, &rules);
+        assert_eq!(warnings.len(), 2);
+        // Each warning points at the word of its own rule.
+        assert_eq!(warnings[0].word, 2);
+        assert_eq!(warnings[1].word, 7);
+        assert_eq!(
+            warnings[1].to_string(),
+            "No counter named rx_pakets, did you mean `rx_packets`?"
+        );
+        assert_eq!(
+            warnings[0].show(&words).lines().last(),
+            Some("               ^^^^^^^^^")
+        );
     }
-    Ok(ret)
 }
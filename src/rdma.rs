@@ -0,0 +1,96 @@
+use crate::ct;
+
+use std::path::Path;
+
+const SYS_CLASS_INFINIBAND: &str = "/sys/class/infiniband";
+
+// Port counter directories. hw_counters are driver-specific, counters are the standard IB PMA
+// ones.
+static COUNTER_DIRS: [&str; 2] = ["hw_counters", "counters"];
+
+// PMA data counters count in units of 4 octets.
+static DATA_COUNTERS: [&str; 2] = ["port_xmit_data", "port_rcv_data"];
+
+#[derive(Debug)]
+pub struct RdmaStat {
+    pub dev: String,
+    pub port: String,
+    pub name: String,
+    pub value: u64,
+    pub default_unit: ct::UnitChain,
+}
+
+fn dir_entries(path: &Path) -> Result<Vec<String>, String> {
+    let mut names = Vec::new();
+    match std::fs::read_dir(path) {
+        Ok(entries) => {
+            for entry in entries {
+                let entry = entry.map_err(|e| format!("{}: {}", path.display(), e))?;
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        // No RDMA devices, or a port without hw_counters.
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    }
+    names.sort();
+    Ok(names)
+}
+
+fn push_counters(
+    stats: &mut Vec<RdmaStat>,
+    dev: &String,
+    port: &String,
+    dir: &Path,
+) -> Result<(), String> {
+    for name in dir_entries(dir)? {
+        // Some counters are not supported by the device and fail to read, and hw_counters has a
+        // "lifespan" knob, which is not a counter. Skip whatever doesn't read as a number.
+        let value = match std::fs::read_to_string(dir.join(&name))
+            .ok()
+            .and_then(|text| text.trim().parse::<u64>().ok())
+        {
+            Some(value) if name != "lifespan" => value,
+            _ => continue,
+        };
+        let (value, default_unit) = if DATA_COUNTERS.contains(&name.as_str()) {
+            (value.wrapping_mul(4), ct::unit_bytes_bits_ps())
+        } else if name.ends_with("_packets") || name.ends_with("_pkts") {
+            (value, ct::unit_packets_ps())
+        } else {
+            (value, ct::unit_units_ps())
+        };
+        stats.push(RdmaStat {
+            dev: dev.clone(),
+            port: port.clone(),
+            name: name,
+            value: value,
+            default_unit: default_unit,
+        });
+    }
+    Ok(())
+}
+
+// Read port counters of all RDMA devices under the given directory. This is
+// /sys/class/infiniband normally.
+pub fn stats_in(dir: &Path) -> Result<Vec<RdmaStat>, String> {
+    let mut stats = Vec::new();
+    for dev in dir_entries(dir)? {
+        let ports_dir = dir.join(&dev).join("ports");
+        for port in dir_entries(&ports_dir)? {
+            for counter_dir in COUNTER_DIRS.iter() {
+                push_counters(
+                    &mut stats,
+                    &dev,
+                    &port,
+                    &ports_dir.join(&port).join(counter_dir),
+                )?;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+pub fn stats() -> Result<Vec<RdmaStat>, String> {
+    stats_in(Path::new(SYS_CLASS_INFINIBAND))
+}
@@ -0,0 +1,221 @@
+use crate::ct;
+
+use std::io::BufRead;
+use std::path::Path;
+
+const PROC_NET: &str = "/proc/net";
+
+#[derive(Debug)]
+pub struct SnmpStat {
+    pub group: String,
+    pub name: String,
+    pub value: u64,
+    pub default_unit: ct::UnitChain,
+}
+
+// Most of the SNMP counters are monotonic event counters. These are the exceptions: configuration
+// values and gauges, which make no sense as a rate.
+static GAUGES: [&str; 7] = [
+    "Ip.Forwarding",
+    "Ip.DefaultTTL",
+    "Tcp.RtoAlgorithm",
+    "Tcp.RtoMin",
+    "Tcp.RtoMax",
+    "Tcp.MaxConn",
+    "Tcp.CurrEstab",
+];
+
+fn push_stat(stats: &mut Vec<SnmpStat>, group: &str, name: &str, value: &str) {
+    // Some fields (e.g. Tcp.MaxConn) can be -1. These are not counters, skip them.
+    let value = match value.parse::<u64>() {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+    let full_name = format!("{}.{}", group, name);
+    let default_unit = if GAUGES.contains(&full_name.as_str()) {
+        ct::unit_units()
+    } else {
+        ct::unit_units_ps()
+    };
+    stats.push(SnmpStat {
+        group: group.to_string(),
+        name: name.to_string(),
+        value: value,
+        default_unit: default_unit,
+    });
+}
+
+// /proc/net/snmp and /proc/net/netstat consist of pairs of lines. The first line of each pair
+// holds the counter names, the second one the values:
+//
+//     Tcp: RtoAlgorithm RtoMin RtoMax MaxConn ActiveOpens ...
+//     Tcp: 1 200 120000 -1 13 ...
+pub fn parse_snmp<R: BufRead>(reader: R) -> Result<Vec<SnmpStat>, String> {
+    let mut stats = Vec::new();
+    let mut lines = reader.lines();
+    while let Some(names) = lines.next() {
+        let names = names.map_err(|e| e.to_string())?;
+        let values = match lines.next() {
+            Some(values) => values.map_err(|e| e.to_string())?,
+            None => return Err(format!("Missing value line after: {}", names)),
+        };
+
+        let mut names = names.split_whitespace();
+        let mut values = values.split_whitespace();
+        let group = match (names.next(), values.next()) {
+            (Some(g1), Some(g2)) if g1 == g2 && g1.ends_with(':') => g1.trim_end_matches(':'),
+            (g1, g2) => {
+                return Err(format!(
+                    "Mismatched SNMP group names: {:?} vs. {:?}",
+                    g1.unwrap_or(""),
+                    g2.unwrap_or("")
+                ))
+            }
+        };
+
+        for (name, value) in names.zip(values) {
+            push_stat(&mut stats, group, name, value);
+        }
+    }
+    Ok(stats)
+}
+
+// /proc/net/snmp6 has one counter per line, the group is encoded in the name:
+//
+//     Ip6InReceives                   	5
+//     Icmp6InMsgs                     	0
+//     UdpLite6InDatagrams             	0
+//
+// Split the name after the "6" to get the group.
+pub fn parse_snmp6<R: BufRead>(reader: R) -> Result<Vec<SnmpStat>, String> {
+    let mut stats = Vec::new();
+    for line in reader.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        let mut fields = line.split_whitespace();
+        let (full_name, value) = match (fields.next(), fields.next()) {
+            (Some(full_name), Some(value)) => (full_name, value),
+            (None, _) => continue,
+            (Some(full_name), None) => return Err(format!("Missing value for {}", full_name)),
+        };
+        let (group, name) = match full_name.find('6') {
+            Some(pos) => full_name.split_at(pos + 1),
+            None => ("Ip6", full_name),
+        };
+        push_stat(&mut stats, group, name, value);
+    }
+    Ok(stats)
+}
+
+fn read_file<F>(path: &Path, parse: F) -> Result<Vec<SnmpStat>, String>
+where
+    F: Fn(std::io::BufReader<std::fs::File>) -> Result<Vec<SnmpStat>, String>,
+{
+    match std::fs::File::open(path) {
+        Ok(file) => {
+            parse(std::io::BufReader::new(file)).map_err(|e| format!("{}: {}", path.display(), e))
+        }
+        // E.g. snmp6 is missing when IPv6 is disabled.
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+// Read snmp, snmp6 and netstat files from the given directory. This is /proc/net normally, but
+// can be pointed at a directory with fixture files.
+pub fn stats_in(dir: &Path) -> Result<Vec<SnmpStat>, String> {
+    let mut stats = read_file(&dir.join("snmp"), parse_snmp)?;
+    stats.append(&mut read_file(&dir.join("snmp6"), parse_snmp6)?);
+    stats.append(&mut read_file(&dir.join("netstat"), parse_snmp)?);
+    Ok(stats)
+}
+
+pub fn stats() -> Result<Vec<SnmpStat>, String> {
+    stats_in(Path::new(PROC_NET))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'a>(stats: &'a [SnmpStat], group: &str, name: &str) -> Option<&'a SnmpStat> {
+        stats
+            .iter()
+            .find(|stat| stat.group == group && stat.name == name)
+    }
+
+    #[test]
+    fn snmp() {
+        let text = "\
+Ip: Forwarding DefaultTTL InReceives InHdrErrors
+Ip: 2 64 7704 3
+Tcp: RtoAlgorithm RtoMin RtoMax MaxConn ActiveOpens RetransSegs
+Tcp: 1 200 120000 -1 13 42
+";
+        let stats = parse_snmp(text.as_bytes()).unwrap();
+        assert_eq!(stats.len(), 9);
+        assert_eq!(find(&stats, "Ip", "InReceives").unwrap().value, 7704);
+        assert_eq!(find(&stats, "Tcp", "RetransSegs").unwrap().value, 42);
+        // -1 is not a counter.
+        assert!(find(&stats, "Tcp", "MaxConn").is_none());
+
+        let forwarding = find(&stats, "Ip", "Forwarding").unwrap();
+        assert_eq!(forwarding.default_unit.freq, ct::UFreq::AsIs);
+        let retrans = find(&stats, "Tcp", "RetransSegs").unwrap();
+        assert_eq!(retrans.default_unit.freq, ct::UFreq::PerSecond);
+    }
+
+    #[test]
+    fn netstat() {
+        let text = "\
+TcpExt: SyncookiesSent SyncookiesRecv ListenOverflows ListenDrops
+TcpExt: 0 0 11 12
+IpExt: InNoRoutes InTruncatedPkts
+IpExt: 5 0
+";
+        let stats = parse_snmp(text.as_bytes()).unwrap();
+        assert_eq!(find(&stats, "TcpExt", "ListenDrops").unwrap().value, 12);
+        assert_eq!(find(&stats, "IpExt", "InNoRoutes").unwrap().value, 5);
+    }
+
+    #[test]
+    fn snmp_malformed() {
+        assert!(parse_snmp("Ip: Forwarding DefaultTTL\n".as_bytes()).is_err());
+        assert!(parse_snmp("Ip: Forwarding\nTcp: 1\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn snmp6() {
+        let text = "\
+Ip6InReceives                   \t5
+Ip6InHdrErrors                  \t0
+Icmp6InMsgs                     \t7
+UdpLite6InDatagrams             \t9
+
+";
+        let stats = parse_snmp6(text.as_bytes()).unwrap();
+        assert_eq!(stats.len(), 4);
+        assert_eq!(find(&stats, "Ip6", "InReceives").unwrap().value, 5);
+        assert_eq!(find(&stats, "Icmp6", "InMsgs").unwrap().value, 7);
+        assert_eq!(find(&stats, "UdpLite6", "InDatagrams").unwrap().value, 9);
+
+        assert!(parse_snmp6("Ip6InReceives\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn fixture_dir() {
+        let dir = std::env::temp_dir().join(format!("stas-snmp-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("snmp"),
+            "Udp: InDatagrams RcvbufErrors\nUdp: 10 2\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("netstat"), "TcpExt: ListenDrops\nTcpExt: 3\n").unwrap();
+
+        // snmp6 is missing, as when IPv6 is disabled.
+        let stats = stats_in(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(find(&stats, "Udp", "RcvbufErrors").unwrap().value, 2);
+        assert_eq!(find(&stats, "TcpExt", "ListenDrops").unwrap().value, 3);
+    }
+}
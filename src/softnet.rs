@@ -0,0 +1,156 @@
+use crate::ct;
+
+use std::io::BufRead;
+use std::path::Path;
+
+const SOFTNET_STAT: &str = "/proc/net/softnet_stat";
+
+#[derive(Debug)]
+pub struct SoftnetStat {
+    pub cpu: u32,
+    pub name: String,
+    pub value: u64,
+    pub default_unit: ct::UnitChain,
+}
+
+// Column layout of /proc/net/softnet_stat. The file has grown over time, and older kernels simply
+// have fewer columns. Columns 3..8 are long-unused placeholders that are always zero.
+//
+// - 0..=2: processed, dropped, time_squeeze (always present)
+// - 9: received_rps (since 2.6.35)
+// - 10: flow_limit_count (since 3.11)
+// - 11: softnet_backlog_len (since 5.10)
+// - 12: CPU index (since 5.10; before that, CPU is the line number, which is wrong with offline
+//   CPUs, because those are skipped)
+// - 13, 14: input_qlen, process_qlen (since 6.2)
+enum Column {
+    Counter(&'static str),
+    Gauge(&'static str),
+}
+
+const CPU_INDEX_COLUMN: usize = 12;
+
+static COLUMNS: [(usize, Column); 8] = [
+    (0, Column::Counter("processed")),
+    (1, Column::Counter("dropped")),
+    (2, Column::Counter("time_squeeze")),
+    (9, Column::Counter("received_rps")),
+    (10, Column::Counter("flow_limit_count")),
+    (11, Column::Gauge("backlog_len")),
+    (13, Column::Gauge("input_qlen")),
+    (14, Column::Gauge("process_qlen")),
+];
+
+pub fn parse_softnet_stat<R: BufRead>(reader: R) -> Result<Vec<SoftnetStat>, String> {
+    let mut stats = Vec::new();
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let fields = line
+            .split_whitespace()
+            .map(|field| u64::from_str_radix(field, 16))
+            .collect::<Result<Vec<u64>, _>>()
+            .map_err(|e| format!("line {}: {}", lineno + 1, e))?;
+        if fields.is_empty() {
+            continue;
+        }
+        if fields.len() < 3 {
+            return Err(format!(
+                "line {}: expected at least 3 columns, got {}",
+                lineno + 1,
+                fields.len()
+            ));
+        }
+
+        let cpu = if CPU_INDEX_COLUMN < fields.len() {
+            fields[CPU_INDEX_COLUMN] as u32
+        } else {
+            lineno as u32
+        };
+
+        for (idx, col) in COLUMNS.iter() {
+            if *idx >= fields.len() {
+                break;
+            }
+            let (name, default_unit) = match col {
+                Column::Counter(name) => (name, ct::unit_packets_ps()),
+                Column::Gauge(name) => (name, ct::unit_packets()),
+            };
+            stats.push(SoftnetStat {
+                cpu: cpu,
+                name: name.to_string(),
+                value: fields[*idx],
+                default_unit: default_unit,
+            });
+        }
+    }
+    Ok(stats)
+}
+
+pub fn stats_from(path: &Path) -> Result<Vec<SoftnetStat>, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_softnet_stat(std::io::BufReader::new(file))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn stats() -> Result<Vec<SoftnetStat>, String> {
+    stats_from(Path::new(SOFTNET_STAT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(stats: &[SoftnetStat], cpu: u32) -> Vec<(&str, u64)> {
+        stats
+            .iter()
+            .filter(|stat| stat.cpu == cpu)
+            .map(|stat| (stat.name.as_str(), stat.value))
+            .collect()
+    }
+
+    #[test]
+    fn columns_6_2() {
+        // CPU 1 is offline, the CPU index column says which line is which.
+        let text = "\
+00001e2c 00000001 00000002 00000000 00000000 00000000 00000000 00000000 00000000 00000003 00000004 00000005 00000000 00000006 00000007
+000000ff 00000000 00000010 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000002 00000000 00000000
+";
+        let stats = parse_softnet_stat(text.as_bytes()).unwrap();
+        assert_eq!(
+            values(&stats, 0),
+            vec![
+                ("processed", 0x1e2c),
+                ("dropped", 1),
+                ("time_squeeze", 2),
+                ("received_rps", 3),
+                ("flow_limit_count", 4),
+                ("backlog_len", 5),
+                ("input_qlen", 6),
+                ("process_qlen", 7),
+            ]
+        );
+        assert_eq!(values(&stats, 1), vec![]);
+        assert_eq!(values(&stats, 2)[2], ("time_squeeze", 0x10));
+    }
+
+    #[test]
+    fn columns_3_11() {
+        let text = "\
+00000010 00000001 00000002 00000000 00000000 00000000 00000000 00000000 00000000 00000003 00000004
+00000020 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000
+";
+        let stats = parse_softnet_stat(text.as_bytes()).unwrap();
+        assert_eq!(stats.len(), 10);
+        assert_eq!(values(&stats, 0)[4], ("flow_limit_count", 4));
+        assert_eq!(values(&stats, 1)[0], ("processed", 0x20));
+
+        let processed = stats.iter().find(|stat| stat.name == "processed").unwrap();
+        assert_eq!(processed.default_unit.freq, ct::UFreq::PerSecond);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(parse_softnet_stat("00000010 00000001\n".as_bytes()).is_err());
+        assert!(parse_softnet_stat("00000010 0000000x 00000000\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use crate::ethtool_ss;
+
+// Drivers report XDP counters under their own ethtool names, some as totals, some per queue. Each
+// normalized name has a list of candidate patterns. The first pattern that matches any of the
+// interface's ethtool counters is used, and all counters matching it are summed. Totals are thus
+// listed before per-queue patterns, so that drivers that have both are not counted twice.
+static XDP_NAMES: [(&str, &[&str]); 9] = [
+    ("xdp_pass", &["rx_xdp_pass", "queue_*_rx_xdp_pass"]),
+    (
+        "xdp_drop",
+        &["rx_xdp_drop", "rx_queue_*_xdp_drops", "queue_*_rx_xdp_drop"],
+    ),
+    ("xdp_aborted", &["rx_xdp_aborted", "queue_*_rx_xdp_aborted"]),
+    (
+        "xdp_tx",
+        &[
+            "rx_xdp_tx_xmit",
+            "rx_xdp_tx",
+            "rx_queue_*_xdp_tx",
+            "queue_*_rx_xdp_tx",
+        ],
+    ),
+    (
+        "xdp_tx_errors",
+        &[
+            "rx_xdp_tx_err",
+            "rx_xdp_tx_errors",
+            "rx_queue_*_xdp_tx_errors",
+        ],
+    ),
+    (
+        "xdp_redirect",
+        &[
+            "rx_xdp_redirect",
+            "rx_queue_*_xdp_redirects",
+            "rx_queue_*_xdp_redirect",
+            "queue_*_rx_xdp_redirect",
+        ],
+    ),
+    ("xdp_redirect_errors", &["rx_xdp_redirect_errors"]),
+    ("xdp_xmit", &["tx_xdp_xmit", "tx_queue_*_xdp_tx"]),
+    (
+        "xdp_xmit_errors",
+        &[
+            "tx_xdp_err",
+            "tx_xdp_xmit_errors",
+            "tx_queue_*_xdp_tx_drops",
+        ],
+    ),
+];
+
+// XDP counters of `ifname` under the normalized names. Counters that the driver doesn't report
+// are omitted.
+pub fn stats_for(ifname: &String) -> Vec<ethtool_ss::Stat> {
+    let ethtool_stats = ethtool_ss::stats_for(ifname);
+    let mut stats = Vec::new();
+    for (name, candidates) in XDP_NAMES.iter() {
+        for candidate in candidates.iter() {
+            let pat = glob::Pattern::new(candidate).unwrap();
+            let mut matching = ethtool_stats
+                .iter()
+                .filter(|stat| pat.matches(&stat.name))
+                .peekable();
+            if matching.peek().is_some() {
+                stats.push(ethtool_ss::Stat {
+                    name: name.to_string(),
+                    value: matching.map(|stat| stat.value).sum(),
+                });
+                break;
+            }
+        }
+    }
+    stats
+}